@@ -0,0 +1,405 @@
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::nodes::{CentroidAccumulator, CentroidMethod, Node};
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+
+/// how the distance between two clusters follows from the distances of their members
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Linkage {
+    /// closest pair of members, follows chains of nearby points
+    Single,
+    /// farthest pair of members, compact clusters of similar diameter
+    Complete,
+    /// mean over all pairs of members (UPGMA), each pair weighted by the product of the
+    /// members' weights
+    #[default]
+    Average,
+    /// Ward's minimum variance update of Lance and Williams applied to the metric
+    /// distances with the clusters' summed weights as their size, favours clusters of
+    /// similar size. only exact for euclidean distances,
+    /// on the sphere it is a close approximation at city scale
+    Ward,
+}
+
+impl Linkage {
+    /// distance from the union of x and y to i, Lance-Williams form
+    fn update(&self, dxi: f64, dyi: f64, dxy: f64, sx: f64, sy: f64, si: f64) -> f64 {
+        match self {
+            Linkage::Single => dxi.min(dyi),
+            Linkage::Complete => dxi.max(dyi),
+            Linkage::Average => (sx * dxi + sy * dyi) / (sx + sy),
+            Linkage::Ward => {
+                let t = sx + sy + si;
+                (((sx + si) * dxi * dxi + (sy + si) * dyi * dyi - si * dxy * dxy) / t)
+                    .max(0.0)
+                    .sqrt()
+            }
+        }
+    }
+}
+
+/// builder for agglomerative hierarchical clustering
+///
+/// every data point starts as its own cluster and the two closest clusters are merged
+/// until one is left. the merges form a [Dendrogram] that can be cut at any number of
+/// clusters or any distance without clustering again, e.g. country, metro and
+/// neighbourhood level from a single fit
+///
+/// holds an n * n distance matrix, so it is meant for up to a few thousand data points
+///
+/// the weights of the data points count in [Linkage::Average] and [Linkage::Ward], single
+/// and complete linkage only look at the distances
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::agglomerative::{Agglomerative, Linkage};
+/// use coordinate_cluster::distance::{Haversine, Units};
+///
+/// let pings = [
+///     (40.7128, -74.0060), // manhattan
+///     (40.7130, -74.0050),
+///     (40.6782, -73.9442), // brooklyn
+///     (40.6790, -73.9450),
+///     (51.5074, -0.1278),  // london
+///     (51.5080, -0.1270),
+/// ];
+/// let dendrogram = Agglomerative::new()
+///     .linkage(Linkage::Average)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(dendrogram.merges.len(), pings.len() - 1);
+///
+/// let cities = dendrogram.cut_count(2, &pings).unwrap();
+/// assert_eq!(cities.labels, vec![0, 0, 0, 0, 1, 1]);
+/// let boroughs = dendrogram.cut_distance(1.0, &pings).unwrap();
+/// assert_eq!(boroughs.labels, vec![0, 0, 1, 1, 2, 2]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Agglomerative<M = Haversine> {
+    linkage: Linkage,
+    centroid: Option<CentroidMethod>,
+    metric: M,
+}
+
+impl Agglomerative<Haversine> {
+    /// [Linkage::Average] over [Haversine]
+    pub fn new() -> Self {
+        Self {
+            linkage: Linkage::default(),
+            centroid: None,
+            metric: Haversine::default(),
+        }
+    }
+}
+
+impl Default for Agglomerative<Haversine> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> Agglomerative<M> {
+    pub fn linkage(mut self, linkage: Linkage) -> Self {
+        self.linkage = linkage;
+        self
+    }
+
+    /// how the node locations of a cut are found, defaults to [CentroidMethod::Spherical]
+    /// for lat/lng metrics and [CentroidMethod::Planar] otherwise
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = Some(centroid);
+        self
+    }
+
+    /// swap the distance metric, merge distances are in its units
+    pub fn metric<M2>(self, metric: M2) -> Agglomerative<M2> {
+        Agglomerative {
+            linkage: self.linkage,
+            centroid: self.centroid,
+            metric,
+        }
+    }
+
+    pub fn fit<T, DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<Dendrogram<T, M>, ClusterError>
+    where
+        T: LatLngType,
+        DATAPOINT: UserDataType<T>,
+        M: DistanceMetric<T> + Clone,
+    {
+        validate_data_points(1, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let matrix = parallel::distance_matrix(&coords, &self.metric);
+        let merges = nearest_neighbor_chain(matrix, &weights, self.linkage);
+        Ok(Dendrogram {
+            merges: relabel(merges, coords.len()),
+            leaves: coords.len(),
+            linkage: self.linkage,
+            centroid: self
+                .centroid
+                .unwrap_or_else(|| CentroidMethod::for_metric(&self.metric)),
+            metric: self.metric.clone(),
+        })
+    }
+}
+
+/// one step of the hierarchy
+///
+/// clusters are numbered like scipy does, the data points are 0..n and the cluster
+/// created by `merges[i]` is n + i
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Merge<T: LatLngType> {
+    /// the lower numbered of the two clusters merged
+    pub left: usize,
+    pub right: usize,
+    /// linkage distance between the two, in the units of the metric
+    pub distance: T,
+    /// number of data points in the merged cluster
+    pub size: usize,
+}
+
+/// result of [Agglomerative::fit], the full merge history of the data points
+#[derive(Clone, Debug)]
+pub struct Dendrogram<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// the n - 1 merges by ascending distance
+    pub merges: Vec<Merge<T>>,
+    /// number of data points that were fitted
+    pub leaves: usize,
+    pub linkage: Linkage,
+    /// how the node locations of a cut are found
+    pub centroid: CentroidMethod,
+    pub metric: M,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> Dendrogram<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.metric.unit()
+    }
+
+    /// cluster of every data point once only `k` clusters are left, numbered by the
+    /// first data point of each cluster in input order
+    pub fn labels_for_count(&self, k: usize) -> Result<Vec<usize>, ClusterError> {
+        if k == 0 {
+            return Err(ClusterError::ZeroClusters);
+        }
+        if k > self.leaves {
+            return Err(ClusterError::TooManyClusters { k, n: self.leaves });
+        }
+        Ok(self.labels_after(self.leaves - k))
+    }
+
+    /// cluster of every data point after every merge no farther apart than `threshold`,
+    /// numbered by the first data point of each cluster in input order
+    pub fn labels_for_distance(&self, threshold: T) -> Result<Vec<usize>, ClusterError> {
+        let value = threshold.to_f64();
+        if value.is_nan() || value < 0.0 {
+            return Err(ClusterError::InvalidThreshold { threshold: value });
+        }
+        let merged = self
+            .merges
+            .partition_point(|merge| merge.distance <= threshold);
+        Ok(self.labels_after(merged))
+    }
+
+    /// the hierarchy cut at `k` clusters as nodes at the centroid of their children, borrowing the data points that were fitted
+    pub fn cut_count<'a, DATAPOINT>(
+        &self,
+        k: usize,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        let labels = self.labels_for_count(k)?;
+        self.cluster(labels, data_points)
+    }
+
+    /// the hierarchy cut at `threshold`, see [Dendrogram::labels_for_distance]
+    pub fn cut_distance<'a, DATAPOINT>(
+        &self,
+        threshold: T,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        let labels = self.labels_for_distance(threshold)?;
+        self.cluster(labels, data_points)
+    }
+
+    /// labels after applying the first `merged` merges
+    fn labels_after(&self, merged: usize) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.leaves + merged).collect();
+        for (step, merge) in self.merges[..merged].iter().enumerate() {
+            parent[merge.left] = self.leaves + step;
+            parent[merge.right] = self.leaves + step;
+        }
+        let mut root_label = vec![usize::MAX; parent.len()];
+        let mut next = 0;
+        (0..self.leaves)
+            .map(|leaf| {
+                let mut root = leaf;
+                while parent[root] != root {
+                    root = parent[root];
+                }
+                if root_label[root] == usize::MAX {
+                    root_label[root] = next;
+                    next += 1;
+                }
+                root_label[root]
+            })
+            .collect()
+    }
+
+    fn cluster<'a, DATAPOINT>(
+        &self,
+        labels: Vec<usize>,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        if data_points.len() != self.leaves {
+            return Err(ClusterError::LengthMismatch {
+                expected: self.leaves,
+                found: data_points.len(),
+            });
+        }
+        let k = labels.iter().max().map_or(0, |&max| max + 1);
+        let mut accumulators = vec![CentroidAccumulator::default(); k];
+        for (data_point, &label) in data_points.iter().zip(&labels) {
+            accumulators[label].push(data_point.get_coords(), data_point.weight());
+        }
+        let mut nodes: Vec<Node<T, &DATAPOINT>> = accumulators
+            .iter()
+            .map(|accumulator| {
+                let (lat, lng) = accumulator.centroid(self.centroid).unwrap_or_default();
+                Node::new(lat, lng, Vec::with_capacity(accumulator.count()))
+            })
+            .collect();
+        for (data_point, &label) in data_points.iter().zip(&labels) {
+            let node = &mut nodes[label];
+            node.total_distance += self
+                .metric
+                .distance(&node.location, &data_point.get_coords())
+                * T::from_f64(data_point.weight());
+            node.push_child(&data_point);
+        }
+        Ok(Cluster {
+            nodes,
+            labels,
+            unit: self.metric.unit(),
+        })
+    }
+}
+
+/// merges found by the nearest neighbour chain, as (slot, slot, distance) where a slot is
+/// the data point whose row holds the cluster. not in distance order
+///
+/// every [Linkage] is reducible, so merging mutual nearest neighbours as they are found
+/// gives the same hierarchy as always merging the globally closest pair
+fn nearest_neighbor_chain(
+    mut matrix: Vec<f64>,
+    weights: &[f64],
+    linkage: Linkage,
+) -> Vec<(usize, usize, f64)> {
+    let n = weights.len();
+    if linkage == Linkage::Ward {
+        // the ward distance of two single points, which is the plain distance at weight 1
+        for i in 0..n {
+            for j in 0..n {
+                matrix[i * n + j] *=
+                    (2.0 * weights[i] * weights[j] / (weights[i] + weights[j])).sqrt();
+            }
+        }
+    }
+    let mut size = weights.to_vec();
+    let mut active = vec![true; n];
+    let mut merges = Vec::with_capacity(n.saturating_sub(1));
+    let mut chain: Vec<usize> = Vec::with_capacity(n);
+    while merges.len() + 1 < n {
+        if chain.is_empty() {
+            chain.push(
+                active
+                    .iter()
+                    .position(|&a| a)
+                    .expect("two clusters are left"),
+            );
+        }
+        let (x, y, distance) = loop {
+            let x = chain[chain.len() - 1];
+            // the previous link wins ties so the chain always ends in a mutual pair
+            let (mut y, mut closest) = match chain.len() {
+                1 => (usize::MAX, f64::INFINITY),
+                len => (chain[len - 2], matrix[x * n + chain[len - 2]]),
+            };
+            for other in (0..n).filter(|&other| active[other] && other != x) {
+                if matrix[x * n + other] < closest {
+                    closest = matrix[x * n + other];
+                    y = other;
+                }
+            }
+            if chain.len() > 1 && y == chain[chain.len() - 2] {
+                chain.truncate(chain.len() - 2);
+                break (x.min(y), x.max(y), closest);
+            }
+            chain.push(y);
+        };
+
+        // the merged cluster lives on in row y
+        active[x] = false;
+        for other in (0..n).filter(|&other| active[other] && other != y) {
+            let updated = linkage.update(
+                matrix[x * n + other],
+                matrix[y * n + other],
+                distance,
+                size[x],
+                size[y],
+                size[other],
+            );
+            matrix[y * n + other] = updated;
+            matrix[other * n + y] = updated;
+        }
+        size[y] += size[x];
+        merges.push((x, y, distance));
+    }
+    merges
+}
+
+/// sort the merges by distance and number the clusters they create
+fn relabel<T: LatLngType>(mut merges: Vec<(usize, usize, f64)>, n: usize) -> Vec<Merge<T>> {
+    merges.sort_by(|a, b| a.2.total_cmp(&b.2));
+    let mut parent: Vec<usize> = (0..2 * n).collect();
+    let mut size = vec![1; 2 * n];
+    let find = |parent: &mut Vec<usize>, mut node: usize| {
+        while parent[node] != node {
+            parent[node] = parent[parent[node]];
+            node = parent[node];
+        }
+        node
+    };
+    merges
+        .into_iter()
+        .enumerate()
+        .map(|(step, (x, y, distance))| {
+            let (a, b) = (find(&mut parent, x), find(&mut parent, y));
+            let created = n + step;
+            parent[a] = created;
+            parent[b] = created;
+            size[created] = size[a] + size[b];
+            Merge {
+                left: a.min(b),
+                right: a.max(b),
+                distance: T::from_f64(distance),
+                size: size[created],
+            }
+        })
+        .collect()
+}
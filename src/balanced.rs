@@ -0,0 +1,645 @@
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::init::InitStrategy;
+use crate::kmeans::{self, KMeansModel};
+use crate::nodes::CentroidMethod;
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::{validate_data_points, ConvergenceReport};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// what the capacity of a node counts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CapacityMeasure {
+    /// the number of data points, bounds are rounded inwards to whole points
+    #[default]
+    Count,
+    /// the summed [UserDataType::weight] of the data points
+    Weight,
+}
+
+/// how much a node may hold, in the unit of the [CapacityMeasure]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capacity {
+    pub min: f64,
+    /// may be infinite
+    pub max: f64,
+}
+
+impl Capacity {
+    pub fn at_most(max: f64) -> Self {
+        Self { min: 0.0, max }
+    }
+
+    pub fn between(min: f64, max: f64) -> Self {
+        Self { min, max }
+    }
+
+    /// no bounds, the node takes whatever is closest to it as in plain k-means
+    pub fn unbounded() -> Self {
+        Self {
+            min: 0.0,
+            max: f64::INFINITY,
+        }
+    }
+}
+
+/// the capacities as configured, resolved against the data on fit
+#[derive(Clone, Debug)]
+enum Capacities {
+    /// every node holds an equal share of the total, within a point of it when counting
+    Even,
+    Uniform(Capacity),
+    PerNode(Vec<Capacity>),
+}
+
+/// builder for capacity constrained k-means, every node stays between a min and a max
+/// count or weight of data points
+///
+/// plain k-means gives every data point to its closest centroid, which can leave one
+/// node with half the data. here each round assigns the data points with a min cost flow
+/// that keeps the total weighted distance to the centroids as low as the capacities
+/// allow, then moves the centroids as usual. without capacities every node gets an even
+/// share
+///
+/// with [CapacityMeasure::Weight] the flow may split a data point between nodes, those
+/// points go whole to the closest of their nodes that still has room. the bounds can then
+/// be missed by the weight of the few split points, [FittedBalancedKMeans::loads] holds
+/// what every node ended up with
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::balanced::{BalancedKMeans, Capacity};
+///
+/// let pings = [
+///     (40.7128, -74.0060),
+///     (40.7130, -74.0050),
+///     (40.7120, -74.0070),
+///     (40.7140, -74.0040),
+///     (40.7110, -74.0080),
+///     (40.7150, -74.0030),
+///     (51.5074, -0.1278),
+///     (51.5080, -0.1270),
+/// ];
+/// let fitted = BalancedKMeans::new(2)
+///     .capacity(Capacity::at_most(4.0))
+///     .seed(1)
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(fitted.loads, vec![4.0, 4.0]);
+///
+/// let too_small = BalancedKMeans::new(2).capacity(Capacity::at_most(3.0));
+/// assert!(too_small.fit(&pings).is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct BalancedKMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    k: usize,
+    capacities: Capacities,
+    measure: CapacityMeasure,
+    max_iterations: usize,
+    tolerance: T,
+    init: InitStrategy,
+    centroid: CentroidMethod,
+    metric: M,
+    seed: Option<u64>,
+}
+
+impl<T: LatLngType> BalancedKMeans<T, Haversine> {
+    /// k nodes of even size, at most 100 rounds
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            capacities: Capacities::Even,
+            measure: CapacityMeasure::default(),
+            max_iterations: 100,
+            tolerance: T::default(),
+            init: InitStrategy::default(),
+            centroid: CentroidMethod::default(),
+            metric: Haversine::default(),
+            seed: None,
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> BalancedKMeans<T, M> {
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// the same capacity for every node
+    pub fn capacity(mut self, capacity: Capacity) -> Self {
+        self.capacities = Capacities::Uniform(capacity);
+        self
+    }
+
+    /// one capacity per node, in node order
+    pub fn capacities(mut self, capacities: Vec<Capacity>) -> Self {
+        self.capacities = Capacities::PerNode(capacities);
+        self
+    }
+
+    pub fn measure(mut self, measure: CapacityMeasure) -> Self {
+        self.measure = measure;
+        self
+    }
+
+    /// upper bound on the number of assignment rounds
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// stop once no centroid moves further than this between rounds, in the units of
+    /// the metric
+    pub fn tolerance(mut self, tolerance: T) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn init(mut self, init: InitStrategy) -> Self {
+        self.init = init;
+        self
+    }
+
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = centroid;
+        self
+    }
+
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> BalancedKMeans<T, M2> {
+        BalancedKMeans {
+            k: self.k,
+            capacities: self.capacities,
+            measure: self.measure,
+            max_iterations: self.max_iterations,
+            tolerance: self.tolerance,
+            init: self.init,
+            centroid: self.centroid,
+            metric,
+            seed: self.seed,
+        }
+    }
+
+    /// seed for the initial centroids, the assignment itself is deterministic
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedBalancedKMeans<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        if self.max_iterations == 0 {
+            return Err(ClusterError::ZeroIterations);
+        }
+        validate_data_points(self.k, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        // what every data point adds to the load of its node, and what a unit of that costs
+        // per unit of distance
+        let (supplies, prices) = match self.measure {
+            CapacityMeasure::Count => (vec![1.0; coords.len()], weights.clone()),
+            CapacityMeasure::Weight => (weights.clone(), vec![1.0; coords.len()]),
+        };
+        let bounds = self.bounds(&supplies)?;
+
+        let mut rng = self.seed.map(fastrand::Rng::with_seed).unwrap_or_default();
+        let mut centroids =
+            self.init
+                .weighted_centroids(self.k, &coords, &weights, &self.metric, &mut rng);
+        let mut labels = vec![usize::MAX; coords.len()];
+        let mut report = ConvergenceReport {
+            iterations: 0,
+            converged: false,
+            max_centroid_shift: T::default(),
+        };
+        loop {
+            report.iterations += 1;
+            let table = parallel::centroid_distances(&coords, &centroids, &self.metric);
+            let costs: Vec<f64> = table
+                .iter()
+                .enumerate()
+                .map(|(cell, distance)| distance.to_f64() * prices[cell / self.k])
+                .collect();
+            let next_labels = Flow::new(self.k, &costs, &bounds, &supplies).solve()?;
+            let labels_unchanged = next_labels == labels;
+            labels = next_labels;
+
+            let distances: Vec<T> = (0..coords.len())
+                .map(|point| table[point * self.k + labels[point]])
+                .collect();
+            let (accumulators, total_distances) =
+                parallel::accumulate(self.k, &coords, &weights, &labels, &distances);
+            report.max_centroid_shift = T::default();
+            let next_centroids: Vec<(T, T)> = centroids
+                .iter()
+                .zip(&accumulators)
+                .map(|(&location, accumulator)| {
+                    let centroid = accumulator.centroid(self.centroid).unwrap_or(location);
+                    let shift = self.metric.distance(&location, &centroid);
+                    if shift > report.max_centroid_shift {
+                        report.max_centroid_shift = shift;
+                    }
+                    centroid
+                })
+                .collect();
+
+            report.converged = labels_unchanged || report.max_centroid_shift <= self.tolerance;
+            if report.converged || report.iterations == self.max_iterations {
+                let mut loads = vec![0.0; self.k];
+                for (&label, &supply) in labels.iter().zip(&supplies) {
+                    loads[label] += supply;
+                }
+                let inertia = total_distances
+                    .iter()
+                    .fold(T::default(), |acc, &total| acc + total);
+                let model = KMeansModel {
+                    centroids,
+                    sizes: accumulators.iter().map(|acc| acc.count()).collect(),
+                    total_distances,
+                    inertia,
+                    report,
+                    metric: self.metric.clone(),
+                };
+                return Ok(FittedBalancedKMeans {
+                    labels,
+                    distances,
+                    loads,
+                    model,
+                });
+            }
+            centroids = next_centroids;
+        }
+    }
+
+    /// (min, max) of every node, checked against each other and against the total
+    fn bounds(&self, supplies: &[f64]) -> Result<Vec<(f64, f64)>, ClusterError> {
+        let total: f64 = supplies.iter().sum();
+        let capacities = match &self.capacities {
+            // whole points can't be shared out evenly, every node takes one of the two
+            // counts around the share
+            Capacities::Even if self.measure == CapacityMeasure::Count => {
+                let share = total / self.k as f64;
+                vec![Capacity::between(share.floor(), share.ceil()); self.k]
+            }
+            Capacities::Even => {
+                let share = total / self.k as f64;
+                vec![Capacity::between(share, share); self.k]
+            }
+            Capacities::Uniform(capacity) => vec![*capacity; self.k],
+            Capacities::PerNode(capacities) if capacities.len() != self.k => {
+                return Err(ClusterError::CapacityCount {
+                    k: self.k,
+                    found: capacities.len(),
+                });
+            }
+            Capacities::PerNode(capacities) => capacities.clone(),
+        };
+        let mut bounds = Vec::with_capacity(self.k);
+        for (node, capacity) in capacities.iter().enumerate() {
+            let (min, max) = match self.measure {
+                // nudged so an even share of e.g. 13.000000000000002 points stays 13
+                CapacityMeasure::Count => {
+                    ((capacity.min - 1e-9).ceil(), (capacity.max + 1e-9).floor())
+                }
+                CapacityMeasure::Weight => (capacity.min, capacity.max),
+            };
+            if !(min.is_finite() && min >= 0.0 && max >= min) {
+                return Err(ClusterError::InvalidCapacity {
+                    node,
+                    min: capacity.min,
+                    max: capacity.max,
+                });
+            }
+            bounds.push((min, max));
+        }
+        let min_total: f64 = bounds.iter().map(|bound| bound.0).sum();
+        let max_total: f64 = bounds.iter().map(|bound| bound.1).sum();
+        let slack = 1e-9 * total;
+        if total < min_total - slack || total > max_total + slack {
+            return Err(ClusterError::InfeasibleCapacity {
+                total,
+                min_total,
+                max_total,
+            });
+        }
+        Ok(bounds)
+    }
+}
+
+/// result of [BalancedKMeans::fit], the node of every training data point plus the model
+#[derive(Clone, Debug)]
+pub struct FittedBalancedKMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// index of the node each data point was assigned to, in input order
+    pub labels: Vec<usize>,
+    /// distance of each data point to its node's centroid, in input order
+    pub distances: Vec<T>,
+    /// count or weight of the data points of every node, in the [CapacityMeasure] fitted with
+    pub loads: Vec<f64>,
+    /// predicting with the model picks the closest centroid, regardless of capacity
+    pub model: KMeansModel<T, M>,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> FittedBalancedKMeans<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.model.unit()
+    }
+
+    /// build the node/children view over the data points that were fitted
+    pub fn cluster<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        if data_points.len() != self.labels.len() {
+            return Err(ClusterError::LengthMismatch {
+                expected: self.labels.len(),
+                found: data_points.len(),
+            });
+        }
+        Ok(kmeans::cluster_from_labels(
+            &self.model,
+            &self.labels,
+            &self.distances,
+            data_points,
+        ))
+    }
+}
+
+/// moving a data point's share out of one node into another, at the change in cost per
+/// unit. ordered so the cheapest move is on top of a [BinaryHeap]
+#[derive(Clone, Copy, Debug)]
+struct Move {
+    cost: f64,
+    point: usize,
+}
+
+impl PartialEq for Move {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Move {}
+
+impl PartialOrd for Move {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Move {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then(other.point.cmp(&self.point))
+    }
+}
+
+/// min cost flow from the data points through the nodes into a sink, every edge from a
+/// node into the sink bounded by its capacity
+///
+/// the data points are routed one at a time along the cheapest path in the residual
+/// graph, which keeps the flow optimal for the points routed so far. a path enters a node
+/// and may push shares of other points on to further nodes until one has room, so the
+/// residual graph is searched over the k nodes only, the cheapest move between every
+/// pair of nodes kept in a heap. filling a node up to its min earns a bonus larger than
+/// any path can cost, which fills every min whenever the capacities allow it
+struct Flow<'a> {
+    k: usize,
+    /// cost per unit of every data point in every node, row major by data point
+    costs: &'a [f64],
+    bounds: &'a [(f64, f64)],
+    supplies: &'a [f64],
+    /// the nodes every data point sits in and how much of it sits there
+    shares: Vec<Vec<(usize, f64)>>,
+    loads: Vec<f64>,
+    /// moves out of node a into node b at a * k + b, stale entries are dropped lazily
+    moves: Vec<BinaryHeap<Move>>,
+    bonus: f64,
+    /// loads this close count as equal
+    epsilon: f64,
+}
+
+impl<'a> Flow<'a> {
+    fn new(k: usize, costs: &'a [f64], bounds: &'a [(f64, f64)], supplies: &'a [f64]) -> Self {
+        let highest = costs.iter().fold(0.0_f64, |max, &cost| max.max(cost));
+        let total: f64 = supplies.iter().sum();
+        Self {
+            k,
+            costs,
+            bounds,
+            supplies,
+            shares: vec![vec![]; supplies.len()],
+            loads: vec![0.0; k],
+            moves: vec![BinaryHeap::new(); k * k],
+            // a path visits every node at most once, each step costing at most
+            // 2 * highest
+            bonus: (2 * k + 2) as f64 * highest + 1.0,
+            epsilon: 1e-12 * total,
+        }
+    }
+
+    /// the node of every data point, the one holding the largest share of it when the flow
+    /// split it
+    fn solve(mut self) -> Result<Vec<usize>, ClusterError> {
+        for point in 0..self.supplies.len() {
+            let mut remaining = self.supplies[point];
+            // routed at least once, however light the point is next to the total
+            loop {
+                remaining -= self.route(point, remaining)?;
+                if remaining <= self.share_epsilon(point) {
+                    break;
+                }
+            }
+        }
+        self.labels()
+    }
+
+    /// shares of `point` this small count as gone
+    fn share_epsilon(&self, point: usize) -> f64 {
+        1e-12 * self.supplies[point]
+    }
+
+    fn cost(&self, point: usize, node: usize) -> f64 {
+        self.costs[point * self.k + node]
+    }
+
+    fn share(&self, point: usize, node: usize) -> f64 {
+        self.shares[point]
+            .iter()
+            .find(|share| share.0 == node)
+            .map_or(0.0, |share| share.1)
+    }
+
+    fn add_share(&mut self, point: usize, node: usize, amount: f64) {
+        match self.shares[point].iter_mut().find(|share| share.0 == node) {
+            Some(share) => share.1 += amount,
+            None => {
+                self.shares[point].push((node, amount));
+                for other in (0..self.k).filter(|&other| other != node) {
+                    let cost = self.cost(point, other) - self.cost(point, node);
+                    self.moves[node * self.k + other].push(Move { cost, point });
+                }
+            }
+        }
+    }
+
+    fn remove_share(&mut self, point: usize, node: usize, amount: f64) {
+        let epsilon = self.share_epsilon(point);
+        let shares = &mut self.shares[point];
+        if let Some(index) = shares.iter().position(|share| share.0 == node) {
+            shares[index].1 -= amount;
+            if shares[index].1 <= epsilon {
+                shares.swap_remove(index);
+            }
+        }
+    }
+
+    fn cheapest_move(&mut self, from: usize, to: usize) -> Option<Move> {
+        while let Some(&top) = self.moves[from * self.k + to].peek() {
+            if self.share(top.point, from) > self.share_epsilon(top.point) {
+                return Some(top);
+            }
+            self.moves[from * self.k + to].pop();
+        }
+        None
+    }
+
+    /// cost of ending a path in `node` and how much more it takes at that cost, None when
+    /// it is full
+    fn sink(&self, node: usize) -> Option<(f64, f64)> {
+        let (min, max) = self.bounds[node];
+        let load = self.loads[node];
+        if load < min - self.epsilon {
+            Some((-self.bonus, min - load))
+        } else if load < max - self.epsilon {
+            Some((0.0, max - load))
+        } else {
+            None
+        }
+    }
+
+    /// send as much of `remaining` as the cheapest path takes, returns the amount sent
+    fn route(&mut self, point: usize, remaining: f64) -> Result<f64, ClusterError> {
+        let k = self.k;
+        let mut edges = Vec::with_capacity(k * k);
+        for from in 0..k {
+            for to in (0..k).filter(|&to| to != from) {
+                if let Some(cheapest) = self.cheapest_move(from, to) {
+                    edges.push((from, to, cheapest));
+                }
+            }
+        }
+        // bellman-ford from the data point, the residual graph holds no negative cycle
+        // but moves can cost less than 0
+        let tolerance = 1e-12 * self.bonus;
+        let mut distance: Vec<f64> = (0..k).map(|node| self.cost(point, node)).collect();
+        let mut previous: Vec<Option<(usize, Move)>> = vec![None; k];
+        for _ in 1..k {
+            let mut relaxed = false;
+            for &(from, to, step) in edges.iter() {
+                if distance[from] + step.cost < distance[to] - tolerance {
+                    distance[to] = distance[from] + step.cost;
+                    previous[to] = Some((from, step));
+                    relaxed = true;
+                }
+            }
+            if !relaxed {
+                break;
+            }
+        }
+        let (last, _, room) = (0..k)
+            .filter_map(|node| {
+                self.sink(node)
+                    .map(|(cost, room)| (node, distance[node] + cost, room))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .ok_or_else(|| self.infeasible())?;
+
+        let mut path = vec![];
+        let mut node = last;
+        while let Some((from, step)) = previous[node] {
+            if path.len() == k {
+                // rounding closed a cycle, enter the last node directly instead
+                path.clear();
+                node = last;
+                break;
+            }
+            path.push((from, node, step.point));
+            node = from;
+        }
+        let amount = path
+            .iter()
+            .map(|&(from, _, moved)| self.share(moved, from))
+            .fold(remaining.min(room), f64::min);
+        self.add_share(point, node, amount);
+        for &(from, to, moved) in path.iter() {
+            self.remove_share(moved, from, amount);
+            self.add_share(moved, to, amount);
+        }
+        self.loads[last] += amount;
+        Ok(amount)
+    }
+
+    fn infeasible(&self) -> ClusterError {
+        ClusterError::InfeasibleCapacity {
+            total: self.supplies.iter().sum(),
+            min_total: self.bounds.iter().map(|bound| bound.0).sum(),
+            max_total: self.bounds.iter().map(|bound| bound.1).sum(),
+        }
+    }
+
+    /// whole points keep their node, split ones go heaviest first to the cheapest of their
+    /// nodes that still has room for all of it, or the one holding most of it
+    fn labels(&self) -> Result<Vec<usize>, ClusterError> {
+        let mut labels = vec![0; self.supplies.len()];
+        let mut loads = vec![0.0; self.k];
+        let mut split = vec![];
+        for (point, shares) in self.shares.iter().enumerate() {
+            match shares.as_slice() {
+                [(node, _)] => {
+                    labels[point] = *node;
+                    loads[*node] += self.supplies[point];
+                }
+                _ => split.push(point),
+            }
+        }
+        split.sort_by(|&a, &b| self.supplies[b].total_cmp(&self.supplies[a]));
+        for point in split {
+            if self.shares[point].is_empty() {
+                return Err(self.infeasible());
+            }
+            let mut shares = self.shares[point].clone();
+            shares.sort_by(|a, b| self.cost(point, a.0).total_cmp(&self.cost(point, b.0)));
+            let fits = shares.iter().find(|share| {
+                loads[share.0] + self.supplies[point] <= self.bounds[share.0].1 + self.epsilon
+            });
+            let node = match fits {
+                Some(share) => share.0,
+                None => {
+                    shares
+                        .iter()
+                        .fold(
+                            shares[0],
+                            |best, &share| if share.1 > best.1 { share } else { best },
+                        )
+                        .0
+                }
+            };
+            labels[point] = node;
+            loads[node] += self.supplies[point];
+        }
+        Ok(labels)
+    }
+}
@@ -1,12 +1,20 @@
+use crate::distance::{DistanceMetric, DistanceUnit};
+use crate::init::InitStrategy;
 use crate::user_data::{LatLngType, UserDataType};
 use crate::nodes::Node;
+use crate::parallel;
 
+#[derive(Clone, Debug)]
 pub struct Cluster<T, DATAPOINT>
 where
     T: LatLngType,
     DATAPOINT: UserDataType<T> + Clone,
 {
     pub nodes: Vec<Node<T, DATAPOINT>>,
+    /// index of the node each data point was assigned to, in input order
+    pub labels: Vec<usize>,
+    /// unit of every node's total_distance, None when the metric is not a physical length
+    pub unit: Option<DistanceUnit>,
 }
 
 impl<T, DATAPOINT> Cluster<T, DATAPOINT>
@@ -14,35 +22,54 @@ where
     T: LatLngType,
     DATAPOINT: UserDataType<T> + Clone,
 {
-    pub fn new(k: usize, data_points: &[DATAPOINT], centroids: &[(T, T)]) -> Self {
-        let mut cluster = Self { nodes: vec![] };
+    /// assign the data points to the closest of `centroids`, or when there are none to
+    /// the closest of k starting centroids drawn from `rng`
+    pub fn new<M: DistanceMetric<T>>(
+        k: usize,
+        data_points: &[DATAPOINT],
+        centroids: &[(T, T)],
+        metric: &M,
+        rng: &mut fastrand::Rng,
+    ) -> Self {
+        let mut cluster = Self {
+            nodes: vec![],
+            labels: Vec::with_capacity(data_points.len()),
+            unit: metric.unit(),
+        };
         if !centroids.is_empty() {
             cluster.assign_centroids_to_nodes(centroids);
         } else {
-            cluster.initialize_first_cluster(k, data_points);
+            let centroids = InitStrategy::default().initial_centroids(k, data_points, metric, rng);
+            cluster.assign_centroids_to_nodes(&centroids);
         }
 
         // Assign the reports to the closest node
-        for data_point in data_points.iter() {
-            // determine the closest node to the report
-            let closest_node_and_distance_tuple =
-                cluster.assign_data_point_to_closest_node(data_point);
-            cluster.nodes[closest_node_and_distance_tuple.0].push_child(data_point);
-            cluster.nodes[closest_node_and_distance_tuple.0].total_distance +=
-                closest_node_and_distance_tuple.1;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let locations: Vec<(T, T)> = cluster.nodes.iter().map(|node| node.location).collect();
+        let mut labels = vec![0; coords.len()];
+        let mut distances = vec![T::default(); coords.len()];
+        parallel::assign(&coords, &locations, metric, &mut labels, &mut distances);
+        for ((data_point, &label), &distance) in data_points.iter().zip(&labels).zip(&distances) {
+            cluster.nodes[label].push_child(data_point);
+            cluster.nodes[label].total_distance += distance * T::from_f64(data_point.weight());
         }
+        cluster.labels = labels;
         cluster
     }
 
     /// for one location determine the closest node (k node) to the location
     /// return the index of the node and the distance to the node
-    pub fn assign_data_point_to_closest_node(&self, data_point: &DATAPOINT) -> (usize, T) {
+    pub fn assign_data_point_to_closest_node<M: DistanceMetric<T>>(
+        &self,
+        data_point: &DATAPOINT,
+        metric: &M,
+    ) -> (usize, T) {
         // tuple of k node and the distance to the node
         let mut min_loc: (usize, T) = (usize::default(), T::max_value());
 
         // if the distance to the node is less than the current minimum distance, update the minimum distance
         for (k, node) in self.nodes.iter().enumerate() {
-            let dist = T::haversine(&node.location, &data_point.get_coords());
+            let dist = metric.distance(&node.location, &data_point.get_coords());
             if dist < min_loc.1 {
                 min_loc = (k, dist);
             }
@@ -56,35 +83,27 @@ where
             self.nodes.push(Node::new(centroid.0, centroid.1, vec![]));
         }
     }
+}
 
-    /// Assign k nodes to the cluster
-    fn initialize_first_cluster(&mut self, k: usize, data_points: &[DATAPOINT]) {
-        let mut min_lat = None;
-        let mut max_lat = None;
-        let mut min_lon = None;
-        let mut max_lon = None;
-        for data_point in data_points {
-            let data_point_coords = data_point.get_coords();
-            if min_lat.map(|ml| data_point_coords.0 > ml).unwrap_or(true) {
-                min_lat = Some(data_point_coords.0);
-            }
-            if max_lat.map(|ml| data_point_coords.0 < ml).unwrap_or(true) {
-                max_lat = Some(data_point_coords.0);
-            }
-            if min_lon.map(|ml| data_point_coords.1 > ml).unwrap_or(true) {
-                min_lon = Some(data_point_coords.1);
-            }
-            if max_lon.map(|ml| data_point_coords.1 < ml).unwrap_or(true) {
-                max_lon = Some(data_point_coords.1);
-            }
-        }
-
-        for _ in 0..=k {
-            self.nodes.push(Node::new(
-                T::rand(min_lat, max_lat),
-                T::rand(min_lon, max_lon),
-                vec![],
-            ));
+impl<T, DATAPOINT> Cluster<T, &DATAPOINT>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T> + Clone,
+{
+    /// clone the borrowed children into an owned cluster
+    pub fn cloned(&self) -> Cluster<T, DATAPOINT> {
+        Cluster {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| Node {
+                    location: node.location,
+                    children: node.children.iter().map(|&child| child.clone()).collect(),
+                    total_distance: node.total_distance,
+                })
+                .collect(),
+            labels: self.labels.clone(),
+            unit: self.unit,
         }
     }
 }
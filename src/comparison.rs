@@ -0,0 +1,372 @@
+//! how much two clusterings of the same data points agree, e.g. before and after a
+//! re-tune
+//!
+//! the label based scores only look at which data points end up together, so they do not
+//! care how the clusters are numbered. [match_centroids] pairs up the centroids
+//! themselves and reports how far each one moved
+//!
+//! Example:
+//! ```
+//! use coordinate_cluster::comparison;
+//! use coordinate_cluster::distance::{Haversine, Units};
+//!
+//! let before = [0, 0, 0, 1, 1, 1];
+//! let renumbered = [1, 1, 1, 0, 0, 0];
+//! assert_eq!(comparison::adjusted_rand_index(&before, &renumbered).unwrap(), 1.0);
+//!
+//! let one_moved = [0, 0, 1, 1, 1, 1];
+//! assert!(comparison::adjusted_rand_index(&before, &one_moved).unwrap() < 1.0);
+//!
+//! let old_depots = [(40.7128, -74.0060), (51.5074, -0.1278)];
+//! let new_depots = [(51.5080, -0.1270), (40.7130, -74.0050)];
+//! let matching = comparison::match_centroids(
+//!     &old_depots,
+//!     &new_depots,
+//!     &Haversine::new(Units::kilometers()),
+//! );
+//! assert_eq!(matching.pairs[0].to, 1);
+//! assert!(matching.max_distance < 0.1);
+//! ```
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit};
+use crate::error::ClusterError;
+use crate::user_data::{LatLngType, UserDataType};
+
+/// every score of [compare]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison<T: LatLngType> {
+    pub adjusted_rand_index: f64,
+    pub normalized_mutual_information: f64,
+    pub homogeneity: f64,
+    pub completeness: f64,
+    pub v_measure: f64,
+    pub centroids: CentroidMatching<T>,
+}
+
+/// see [homogeneity_completeness_v_measure]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VMeasure {
+    /// 1 when every cluster of the second labelling only holds data points that share a
+    /// cluster in the first
+    pub homogeneity: f64,
+    /// 1 when the data points of every cluster of the first labelling share a cluster
+    /// in the second
+    pub completeness: f64,
+    /// harmonic mean of the two
+    pub v_measure: f64,
+}
+
+/// one centroid of the first clustering and the one of the second it was matched to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchedCentroid<T: LatLngType> {
+    /// index of the centroid in the first clustering
+    pub from: usize,
+    /// index of the centroid in the second clustering
+    pub to: usize,
+    /// how far it moved, in the units of the metric
+    pub distance: T,
+}
+
+/// result of [match_centroids]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CentroidMatching<T: LatLngType> {
+    /// matched pairs ordered by `from`
+    pub pairs: Vec<MatchedCentroid<T>>,
+    /// centroids of the first clustering left without a partner, when it has more
+    pub unmatched_from: Vec<usize>,
+    /// centroids of the second clustering left without a partner, when it has more
+    pub unmatched_to: Vec<usize>,
+    /// sum of the pairs' distances, the smallest possible over every matching
+    pub total_distance: T,
+    pub max_distance: T,
+    pub unit: Option<DistanceUnit>,
+}
+
+/// every score between two clusterings of the same data points, the nodes' locations
+/// are matched as centroids
+pub fn compare<T, A, B, M>(
+    first: &Cluster<T, A>,
+    second: &Cluster<T, B>,
+    metric: &M,
+) -> Result<Comparison<T>, ClusterError>
+where
+    T: LatLngType,
+    A: UserDataType<T> + Clone,
+    B: UserDataType<T> + Clone,
+    M: DistanceMetric<T>,
+{
+    let contingency = Contingency::new(&first.labels, &second.labels)?;
+    let v_measure = contingency.v_measure();
+    Ok(Comparison {
+        adjusted_rand_index: contingency.adjusted_rand_index(),
+        normalized_mutual_information: contingency.normalized_mutual_information(),
+        homogeneity: v_measure.homogeneity,
+        completeness: v_measure.completeness,
+        v_measure: v_measure.v_measure,
+        centroids: match_centroids(&locations(first), &locations(second), metric),
+    })
+}
+
+fn locations<T, DATAPOINT>(cluster: &Cluster<T, DATAPOINT>) -> Vec<(T, T)>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T> + Clone,
+{
+    cluster.nodes.iter().map(|node| node.location).collect()
+}
+
+/// agreement of the pairs of data points, 1 for identical clusterings up to renumbering
+/// and around 0 for unrelated ones (it can go below)
+pub fn adjusted_rand_index(first: &[usize], second: &[usize]) -> Result<f64, ClusterError> {
+    Ok(Contingency::new(first, second)?.adjusted_rand_index())
+}
+
+/// mutual information over the mean entropy of the two labellings, in [0, 1]
+pub fn normalized_mutual_information(
+    first: &[usize],
+    second: &[usize],
+) -> Result<f64, ClusterError> {
+    Ok(Contingency::new(first, second)?.normalized_mutual_information())
+}
+
+/// how well `second` respects the clusters of `first`, taken as the reference
+///
+/// with [crate::density::DensityClusters] labels, map the noise to a label of its own
+pub fn homogeneity_completeness_v_measure(
+    first: &[usize],
+    second: &[usize],
+) -> Result<VMeasure, ClusterError> {
+    Ok(Contingency::new(first, second)?.v_measure())
+}
+
+/// pair every centroid of `first` with one of `second` so the total distance between the
+/// pairs is as small as possible (the Hungarian algorithm)
+pub fn match_centroids<T, M>(first: &[(T, T)], second: &[(T, T)], metric: &M) -> CentroidMatching<T>
+where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let distances: Vec<Vec<T>> = first
+        .iter()
+        .map(|a| second.iter().map(|b| metric.distance(a, b)).collect())
+        .collect();
+    let pairs: Vec<(usize, usize)> = match first.len() <= second.len() {
+        true => hungarian(first.len(), second.len(), |row, col| {
+            distances[row][col].to_f64()
+        }),
+        false => hungarian(second.len(), first.len(), |row, col| {
+            distances[col][row].to_f64()
+        })
+        .into_iter()
+        .map(|(to, from)| (from, to))
+        .collect(),
+    };
+    let mut pairs: Vec<MatchedCentroid<T>> = pairs
+        .into_iter()
+        .map(|(from, to)| MatchedCentroid {
+            from,
+            to,
+            distance: distances[from][to],
+        })
+        .collect();
+    pairs.sort_by_key(|pair| pair.from);
+    let unmatched = |count: usize, matched: &dyn Fn(usize) -> bool| -> Vec<usize> {
+        (0..count).filter(|&index| !matched(index)).collect()
+    };
+    CentroidMatching {
+        unmatched_from: unmatched(first.len(), &|index| pairs.iter().any(|p| p.from == index)),
+        unmatched_to: unmatched(second.len(), &|index| pairs.iter().any(|p| p.to == index)),
+        total_distance: pairs
+            .iter()
+            .fold(T::default(), |acc, pair| acc + pair.distance),
+        max_distance: pairs.iter().fold(T::default(), |max, pair| {
+            if pair.distance > max {
+                pair.distance
+            } else {
+                max
+            }
+        }),
+        pairs,
+        unit: metric.unit(),
+    }
+}
+
+/// minimum cost assignment of every row to a distinct column, rows <= cols. the O(n² m)
+/// potentials version of the Hungarian algorithm, returns (row, col) pairs
+fn hungarian<F: Fn(usize, usize) -> f64>(rows: usize, cols: usize, cost: F) -> Vec<(usize, usize)> {
+    // 1 based with row/column 0 as the virtual start, as in the textbook formulation
+    let mut u = vec![0.0; rows + 1];
+    let mut v = vec![0.0; cols + 1];
+    let mut row_of = vec![0usize; cols + 1];
+    let mut way = vec![0usize; cols + 1];
+    for row in 1..=rows {
+        row_of[0] = row;
+        let mut col = 0;
+        let mut min_reduced = vec![f64::INFINITY; cols + 1];
+        let mut used = vec![false; cols + 1];
+        loop {
+            used[col] = true;
+            let current_row = row_of[col];
+            let mut delta = f64::INFINITY;
+            let mut next = 0;
+            for other in 1..=cols {
+                if used[other] {
+                    continue;
+                }
+                let reduced = cost(current_row - 1, other - 1) - u[current_row] - v[other];
+                if reduced < min_reduced[other] {
+                    min_reduced[other] = reduced;
+                    way[other] = col;
+                }
+                if min_reduced[other] < delta {
+                    delta = min_reduced[other];
+                    next = other;
+                }
+            }
+            for other in 0..=cols {
+                if used[other] {
+                    u[row_of[other]] += delta;
+                    v[other] -= delta;
+                } else {
+                    min_reduced[other] -= delta;
+                }
+            }
+            col = next;
+            if row_of[col] == 0 {
+                break;
+            }
+        }
+        // flip the augmenting path
+        while col != 0 {
+            let previous = way[col];
+            row_of[col] = row_of[previous];
+            col = previous;
+        }
+    }
+    (1..=cols)
+        .filter(|&col| row_of[col] != 0)
+        .map(|col| (row_of[col] - 1, col - 1))
+        .collect()
+}
+
+/// counts of data points per pair of labels, with empty labels left out
+struct Contingency {
+    n: f64,
+    /// non zero cells
+    cells: Vec<f64>,
+    first_sizes: Vec<f64>,
+    second_sizes: Vec<f64>,
+}
+
+impl Contingency {
+    fn new(first: &[usize], second: &[usize]) -> Result<Self, ClusterError> {
+        if first.len() != second.len() {
+            return Err(ClusterError::LengthMismatch {
+                expected: first.len(),
+                found: second.len(),
+            });
+        }
+        if first.is_empty() {
+            return Err(ClusterError::EmptyInput);
+        }
+        // the non zero cells from the sorted label pairs, a dense table would hold one
+        // cell per pair of labels
+        let mut pairs: Vec<(usize, usize)> =
+            first.iter().copied().zip(second.iter().copied()).collect();
+        pairs.sort_unstable();
+        let mut cells = vec![];
+        let mut start = 0;
+        for end in 1..=pairs.len() {
+            if end == pairs.len() || pairs[end] != pairs[start] {
+                cells.push((end - start) as f64);
+                start = end;
+            }
+        }
+        let sizes = |labels: &[usize]| -> Vec<f64> {
+            let mut counts = vec![0usize; labels.iter().max().map_or(0, |&max| max + 1)];
+            for &label in labels {
+                counts[label] += 1;
+            }
+            counts
+                .into_iter()
+                .filter(|&size| size > 0)
+                .map(|size| size as f64)
+                .collect()
+        };
+        Ok(Self {
+            n: first.len() as f64,
+            cells,
+            first_sizes: sizes(first),
+            second_sizes: sizes(second),
+        })
+    }
+
+    fn adjusted_rand_index(&self) -> f64 {
+        let pairs = |count: &f64| count * (count - 1.0) / 2.0;
+        let index: f64 = self.cells.iter().map(pairs).sum();
+        let first: f64 = self.first_sizes.iter().map(pairs).sum();
+        let second: f64 = self.second_sizes.iter().map(pairs).sum();
+        // a single data point has no pairs, it is clustered the same either way
+        if pairs(&self.n) == 0.0 {
+            return 1.0;
+        }
+        let expected = first * second / pairs(&self.n);
+        let max = (first + second) / 2.0;
+        // both a single cluster, or both all singletons
+        if max == expected {
+            return 1.0;
+        }
+        (index - expected) / (max - expected)
+    }
+
+    fn entropy(sizes: &[f64], n: f64) -> f64 {
+        -sizes
+            .iter()
+            .map(|&size| size / n * (size / n).ln())
+            .sum::<f64>()
+    }
+
+    fn mutual_information(&self) -> f64 {
+        let first = Self::entropy(&self.first_sizes, self.n);
+        let second = Self::entropy(&self.second_sizes, self.n);
+        let joint = Self::entropy(&self.cells, self.n);
+        (first + second - joint).max(0.0)
+    }
+
+    fn normalized_mutual_information(&self) -> f64 {
+        let first = Self::entropy(&self.first_sizes, self.n);
+        let second = Self::entropy(&self.second_sizes, self.n);
+        if first == 0.0 && second == 0.0 {
+            return 1.0;
+        }
+        (self.mutual_information() / ((first + second) / 2.0)).min(1.0)
+    }
+
+    fn v_measure(&self) -> VMeasure {
+        let first = Self::entropy(&self.first_sizes, self.n);
+        let second = Self::entropy(&self.second_sizes, self.n);
+        let information = self.mutual_information();
+        // a labelling with a single cluster carries no information to lose
+        let homogeneity = if first == 0.0 {
+            1.0
+        } else {
+            (information / first).min(1.0)
+        };
+        let completeness = if second == 0.0 {
+            1.0
+        } else {
+            (information / second).min(1.0)
+        };
+        let sum = homogeneity + completeness;
+        let v_measure = if sum == 0.0 {
+            0.0
+        } else {
+            2.0 * homogeneity * completeness / sum
+        };
+        VMeasure {
+            homogeneity,
+            completeness,
+            v_measure,
+        }
+    }
+}
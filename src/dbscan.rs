@@ -0,0 +1,202 @@
+use crate::density::{density_clusters, DensityClusters, NeighborIndex};
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::nodes::CentroidMethod;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+
+/// builder for DBSCAN, density based clusters of any shape without choosing k
+///
+/// a data point whose neighbours within `eps` (itself included) weigh at least
+/// `min_points` is a core point, with the default weight of 1 that is a count of them.
+/// core points within `eps` of each other share a cluster and every
+/// other point within `eps` of a core point joins the first cluster that reaches it.
+/// everything else is noise
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::dbscan::Dbscan;
+/// use coordinate_cluster::distance::{Haversine, Units};
+///
+/// let incidents = [
+///     (40.7128, -74.0060),
+///     (40.7130, -74.0062),
+///     (40.7126, -74.0059),
+///     (51.5074, -0.1278),
+///     (51.5076, -0.1280),
+///     (51.5072, -0.1276),
+///     (35.6895, 139.6917),
+/// ];
+/// let fitted = Dbscan::new(0.5, 3)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .fit(&incidents)
+///     .unwrap();
+/// assert_eq!(fitted.cluster_count, 2);
+/// let clusters = fitted.clusters(&incidents).unwrap();
+/// assert_eq!(clusters.nodes[0].children.len(), 3);
+/// assert_eq!(clusters.noise, vec![&incidents[6]]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Dbscan<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    eps: T,
+    min_points: usize,
+    centroid: Option<CentroidMethod>,
+    metric: M,
+}
+
+impl<T: LatLngType> Dbscan<T, Haversine> {
+    /// `eps` is in the units of the metric, miles for the default [Haversine]
+    pub fn new(eps: T, min_points: usize) -> Self {
+        Self {
+            eps,
+            min_points,
+            centroid: None,
+            metric: Haversine::default(),
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> Dbscan<T, M> {
+    /// how the node locations of the clusters are found, defaults to
+    /// [CentroidMethod::Spherical] for lat/lng metrics and [CentroidMethod::Planar] otherwise
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = Some(centroid);
+        self
+    }
+
+    /// swap the distance metric, `eps` is now read in its units
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> Dbscan<T, M2> {
+        Dbscan {
+            eps: self.eps,
+            min_points: self.min_points,
+            centroid: self.centroid,
+            metric,
+        }
+    }
+
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedDbscan<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        validate_density_parameters(self.eps, self.min_points)?;
+        validate_data_points(1, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let index = NeighborIndex::new(&coords, &self.metric);
+        let min_mass = self.min_points as f64;
+        let mass = |neighbors: &[(usize, T)]| -> f64 {
+            neighbors.iter().map(|&(other, _)| weights[other]).sum()
+        };
+
+        let mut labels: Vec<Option<usize>> = vec![None; coords.len()];
+        let mut visited = vec![false; coords.len()];
+        let mut core = vec![false; coords.len()];
+        let mut cluster_count = 0;
+        let mut neighbors = vec![];
+        let mut frontier = vec![];
+        for start in 0..coords.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            index.within(start, self.eps, &mut neighbors);
+            if mass(&neighbors) < min_mass {
+                continue;
+            }
+            let cluster = cluster_count;
+            cluster_count += 1;
+            core[start] = true;
+            labels[start] = Some(cluster);
+            for &(other, _) in neighbors.iter() {
+                if labels[other].is_none() {
+                    labels[other] = Some(cluster);
+                    frontier.push(other);
+                }
+            }
+            // points already visited were noise so far and stay border points
+            while let Some(point) = frontier.pop() {
+                if visited[point] {
+                    continue;
+                }
+                visited[point] = true;
+                index.within(point, self.eps, &mut neighbors);
+                if mass(&neighbors) < min_mass {
+                    continue;
+                }
+                core[point] = true;
+                for &(other, _) in neighbors.iter() {
+                    if labels[other].is_none() {
+                        labels[other] = Some(cluster);
+                        frontier.push(other);
+                    }
+                }
+            }
+        }
+        Ok(FittedDbscan {
+            labels,
+            core,
+            cluster_count,
+            eps: self.eps,
+            centroid: self
+                .centroid
+                .unwrap_or_else(|| CentroidMethod::for_metric(&self.metric)),
+            metric: self.metric.clone(),
+        })
+    }
+}
+
+/// result of [Dbscan::fit]
+#[derive(Clone, Debug)]
+pub struct FittedDbscan<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// cluster of each data point in input order, None for noise
+    pub labels: Vec<Option<usize>>,
+    /// whether each data point is a core point
+    pub core: Vec<bool>,
+    pub cluster_count: usize,
+    pub eps: T,
+    /// how the node locations of the clusters are found
+    pub centroid: CentroidMethod,
+    pub metric: M,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> FittedDbscan<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.metric.unit()
+    }
+
+    /// build the nodes and the noise set over the data points that were fitted
+    pub fn clusters<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<DensityClusters<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        density_clusters(
+            &self.labels,
+            self.cluster_count,
+            data_points,
+            &self.metric,
+            self.centroid,
+        )
+    }
+}
+
+/// shared checks of the density based builders
+pub(crate) fn validate_density_parameters<T: LatLngType>(
+    eps: T,
+    min_points: usize,
+) -> Result<(), ClusterError> {
+    let eps = eps.to_f64();
+    if !(eps.is_finite() && eps > 0.0) {
+        return Err(ClusterError::InvalidEps { eps });
+    }
+    if min_points == 0 {
+        return Err(ClusterError::ZeroMinPoints);
+    }
+    Ok(())
+}
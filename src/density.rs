@@ -0,0 +1,155 @@
+use crate::distance::{DistanceMetric, DistanceUnit};
+use crate::error::ClusterError;
+use crate::nodes::{CentroidAccumulator, CentroidMethod, Node};
+use crate::user_data::{LatLngType, UserDataType};
+
+/// slack added to the latitude band of a neighbourhood search, in degrees (about 11 m),
+/// keeps rounding in the metric from dropping points right on the edge
+const LATITUDE_BAND_SLACK: f64 = 1e-4;
+
+/// clusters of a density based method over borrowed data points
+///
+/// unlike [crate::Cluster] not every data point belongs to a node, the ones that do
+/// not are kept in `noise`
+#[derive(Clone, Debug)]
+pub struct DensityClusters<T, DATAPOINT>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T> + Clone,
+{
+    /// one node per cluster, located at the centroid of its members
+    pub nodes: Vec<Node<T, DATAPOINT>>,
+    /// data points that belong to no cluster, in input order
+    pub noise: Vec<DATAPOINT>,
+    /// cluster of each data point in input order, None for noise
+    pub labels: Vec<Option<usize>>,
+    /// unit of every node's total_distance, None when the metric is not a physical length
+    pub unit: Option<DistanceUnit>,
+}
+
+/// group `data_points` by `labels`, each node's total distance is measured from the
+/// centroid of its members
+pub(crate) fn density_clusters<'a, T, DATAPOINT, M>(
+    labels: &[Option<usize>],
+    cluster_count: usize,
+    data_points: &'a [DATAPOINT],
+    metric: &M,
+    centroid: CentroidMethod,
+) -> Result<DensityClusters<T, &'a DATAPOINT>, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    if data_points.len() != labels.len() {
+        return Err(ClusterError::LengthMismatch {
+            expected: labels.len(),
+            found: data_points.len(),
+        });
+    }
+    let mut accumulators = vec![CentroidAccumulator::default(); cluster_count];
+    for (data_point, label) in data_points.iter().zip(labels) {
+        if let Some(label) = label {
+            accumulators[*label].push(data_point.get_coords(), data_point.weight());
+        }
+    }
+    let mut nodes: Vec<Node<T, &DATAPOINT>> = accumulators
+        .iter()
+        .map(|accumulator| {
+            let (lat, lng) = accumulator.centroid(centroid).unwrap_or_default();
+            Node::new(lat, lng, Vec::with_capacity(accumulator.count()))
+        })
+        .collect();
+    let mut noise = vec![];
+    for (data_point, label) in data_points.iter().zip(labels) {
+        match label {
+            Some(label) => {
+                let node = &mut nodes[*label];
+                node.total_distance += metric.distance(&node.location, &data_point.get_coords())
+                    * T::from_f64(data_point.weight());
+                node.push_child(&data_point);
+            }
+            None => noise.push(data_point),
+        }
+    }
+    Ok(DensityClusters {
+        nodes,
+        noise,
+        labels: labels.to_vec(),
+        unit: metric.unit(),
+    })
+}
+
+/// distance at which the closest of `neighbors` add up to a weight of `min_mass`, None
+/// when all of them together weigh less. sorts `neighbors` by distance
+pub(crate) fn core_distance<T: LatLngType>(
+    neighbors: &mut [(usize, T)],
+    weights: &[f64],
+    min_mass: f64,
+) -> Option<T> {
+    neighbors.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    let mut mass = 0.0;
+    for &(other, distance) in neighbors.iter() {
+        mass += weights[other];
+        if mass >= min_mass {
+            return Some(distance);
+        }
+    }
+    None
+}
+
+/// radius queries over a set of coordinates
+///
+/// when the metric bounds how far apart in latitude two close points can be, the points
+/// are sorted by latitude and a query only measures the ones inside that band
+pub(crate) struct NeighborIndex<'a, T: LatLngType, M: DistanceMetric<T>> {
+    coords: &'a [(T, T)],
+    metric: &'a M,
+    /// point indices and their latitudes in ascending latitude order
+    by_latitude: Option<(Vec<usize>, Vec<f64>)>,
+}
+
+impl<'a, T: LatLngType, M: DistanceMetric<T>> NeighborIndex<'a, T, M> {
+    pub(crate) fn new(coords: &'a [(T, T)], metric: &'a M) -> Self {
+        let by_latitude = metric.max_latitude_gap(T::default()).map(|_| {
+            let mut order: Vec<usize> = (0..coords.len()).collect();
+            order.sort_by(|&a, &b| {
+                coords[a]
+                    .0
+                    .partial_cmp(&coords[b].0)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let latitudes = order.iter().map(|&i| coords[i].0.to_f64()).collect();
+            (order, latitudes)
+        });
+        Self {
+            coords,
+            metric,
+            by_latitude,
+        }
+    }
+
+    /// every point no further than `radius` from point `index`, itself included, with
+    /// its distance. the order is deterministic but unspecified
+    pub(crate) fn within(&self, index: usize, radius: T, out: &mut Vec<(usize, T)>) {
+        out.clear();
+        let center = self.coords[index];
+        let mut visit = |other: usize| {
+            let distance = self.metric.distance(&center, &self.coords[other]);
+            if distance <= radius {
+                out.push((other, distance));
+            }
+        };
+        let gap = self.metric.max_latitude_gap(radius);
+        match (&self.by_latitude, gap) {
+            (Some((order, latitudes)), Some(gap)) => {
+                let gap = gap + LATITUDE_BAND_SLACK;
+                let lat = center.0.to_f64();
+                let start = latitudes.partition_point(|&other| other < lat - gap);
+                let end = latitudes.partition_point(|&other| other <= lat + gap);
+                order[start..end].iter().for_each(|&other| visit(other));
+            }
+            _ => (0..self.coords.len()).for_each(visit),
+        }
+    }
+}
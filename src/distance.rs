@@ -0,0 +1,332 @@
+use crate::parallel::MaybeSync;
+use crate::user_data::LatLngType;
+
+const METERS_PER_MILE: f64 = 1609.344;
+const LEGACY_EARTH_RADIUS_MILES: f64 = 3960.0;
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+const WGS84_MEAN_RADIUS_METERS: f64 = 6_371_008.8;
+const VINCENTY_MAX_ITERATIONS: usize = 200;
+
+/// unit a distance is expressed in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DistanceUnit {
+    Kilometers,
+    Meters,
+    #[default]
+    Miles,
+    NauticalMiles,
+}
+
+impl DistanceUnit {
+    /// length of one unit in meters
+    pub fn meters(&self) -> f64 {
+        match self {
+            DistanceUnit::Kilometers => 1000.0,
+            DistanceUnit::Meters => 1.0,
+            DistanceUnit::Miles => METERS_PER_MILE,
+            DistanceUnit::NauticalMiles => 1852.0,
+        }
+    }
+
+    /// convert a distance in this unit to `other`
+    pub fn convert(&self, distance: f64, other: DistanceUnit) -> f64 {
+        if *self == other {
+            return distance;
+        }
+        distance * self.meters() / other.meters()
+    }
+}
+
+/// radius of the sphere the spherical metrics measure on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EarthModel {
+    /// the 3960 mile sphere the crate has always used
+    #[default]
+    Legacy,
+    /// WGS84 mean radius (6371.0088 km)
+    Wgs84Mean,
+    /// any other sphere
+    Sphere { radius_meters: f64 },
+}
+
+impl EarthModel {
+    /// radius of the sphere in the given unit
+    pub fn radius(&self, unit: DistanceUnit) -> f64 {
+        match self {
+            EarthModel::Legacy => DistanceUnit::Miles.convert(LEGACY_EARTH_RADIUS_MILES, unit),
+            EarthModel::Wgs84Mean => DistanceUnit::Meters.convert(WGS84_MEAN_RADIUS_METERS, unit),
+            EarthModel::Sphere { radius_meters } => {
+                DistanceUnit::Meters.convert(*radius_meters, unit)
+            }
+        }
+    }
+}
+
+/// unit and earth model used by the spherical metrics
+///
+/// the default is miles on the 3960 mile sphere, which matches [LatLngType::haversine].
+/// every named constructor uses the WGS84 mean radius instead, so [Units::miles] measures
+/// about 0.03% shorter than the default
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Units {
+    pub unit: DistanceUnit,
+    pub earth: EarthModel,
+}
+
+impl Units {
+    pub fn new(unit: DistanceUnit, earth: EarthModel) -> Self {
+        Self { unit, earth }
+    }
+    pub fn kilometers() -> Self {
+        Self::new(DistanceUnit::Kilometers, EarthModel::Wgs84Mean)
+    }
+    pub fn meters() -> Self {
+        Self::new(DistanceUnit::Meters, EarthModel::Wgs84Mean)
+    }
+    /// miles on the WGS84 mean radius (3958.76 mi), unlike [Units::default] which keeps the
+    /// 3960 mile sphere
+    pub fn miles() -> Self {
+        Self::new(DistanceUnit::Miles, EarthModel::Wgs84Mean)
+    }
+    pub fn nautical_miles() -> Self {
+        Self::new(DistanceUnit::NauticalMiles, EarthModel::Wgs84Mean)
+    }
+    /// earth radius in [Units::unit]
+    pub fn radius(&self) -> f64 {
+        self.earth.radius(self.unit)
+    }
+}
+
+/// distance between two (lat, lng) coordinates
+///
+/// the cluster assignment, node total distance and every reported statistic go through
+/// the metric, so it must be cheap to call. closures of the form
+/// `Fn(&(T, T), &(T, T)) -> T` implement this trait as well for user defined metrics.
+/// with the `parallel` feature the metric is shared between threads and has to be [Sync]
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{DistanceMetric, Haversine, Units};
+///
+/// let nyc = (40.7128_f64, -74.0060);
+/// let london = (51.5074_f64, -0.1278);
+/// let miles = Haversine::default().distance(&nyc, &london);
+/// assert!((miles - 3461.0).abs() < 10.0);
+/// let km = Haversine::new(Units::kilometers()).distance(&nyc, &london);
+/// assert!((km - 5570.0).abs() < 10.0);
+///
+/// let manhattan = |a: &(f64, f64), b: &(f64, f64)| (a.0 - b.0).abs() + (a.1 - b.1).abs();
+/// assert_eq!(manhattan.distance(&(0.0, 0.0), &(1.0, 2.0)), 3.0);
+/// ```
+pub trait DistanceMetric<T: LatLngType>: MaybeSync {
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T;
+
+    /// unit of the returned distances, None for metrics that are not a physical
+    /// length (squared or projected distances, most user defined metrics)
+    fn unit(&self) -> Option<DistanceUnit> {
+        None
+    }
+
+    /// whether coordinates are (lat, lng) in degrees and should be range checked
+    fn expects_lat_lng(&self) -> bool {
+        true
+    }
+
+    /// whether d(a, c) <= d(a, b) + d(b, c) always holds, up to rounding.
+    /// lets [crate::kmeans::Algorithm::Elkan] and [crate::kmeans::Algorithm::Hamerly]
+    /// skip distance calls, a wrong `true` gives wrong assignments
+    fn satisfies_triangle_inequality(&self) -> bool {
+        false
+    }
+
+    /// largest latitude difference in degrees between two points at most `distance`
+    /// apart, lets radius searches skip whole latitude bands. None when unknown
+    fn max_latitude_gap(&self, _distance: T) -> Option<f64> {
+        None
+    }
+}
+
+impl<T, F> DistanceMetric<T> for F
+where
+    T: LatLngType,
+    F: Fn(&(T, T), &(T, T)) -> T + MaybeSync,
+{
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T {
+        self(coord1, coord2)
+    }
+}
+
+/// great circle distance on a spherical earth, see [LatLngType::haversine_with_radius]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Haversine {
+    pub units: Units,
+}
+
+impl Haversine {
+    pub fn new(units: Units) -> Self {
+        Self { units }
+    }
+}
+
+impl<T: LatLngType> DistanceMetric<T> for Haversine {
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T {
+        T::haversine_with_radius(coord1, coord2, T::from_f64(self.units.radius()))
+    }
+    fn unit(&self) -> Option<DistanceUnit> {
+        Some(self.units.unit)
+    }
+    fn max_latitude_gap(&self, distance: T) -> Option<f64> {
+        Some((distance.to_f64() / self.units.radius()).to_degrees())
+    }
+    fn satisfies_triangle_inequality(&self) -> bool {
+        true
+    }
+}
+
+/// equirectangular projection approximation of the great circle distance
+///
+/// noticeably cheaper than [Haversine] and accurate for points a few hundred miles apart,
+/// the error grows with distance and towards the poles
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Equirectangular {
+    pub units: Units,
+}
+
+impl Equirectangular {
+    pub fn new(units: Units) -> Self {
+        Self { units }
+    }
+}
+
+impl<T: LatLngType> DistanceMetric<T> for Equirectangular {
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T {
+        let lat1 = coord1.0.to_f64().to_radians();
+        let lat2 = coord2.0.to_f64().to_radians();
+        let mut d_lng = (coord2.1.to_f64() - coord1.1.to_f64()).to_radians();
+        // take the short way around the antimeridian
+        if d_lng > std::f64::consts::PI {
+            d_lng -= 2.0 * std::f64::consts::PI;
+        } else if d_lng < -std::f64::consts::PI {
+            d_lng += 2.0 * std::f64::consts::PI;
+        }
+        let x = d_lng * ((lat1 + lat2) / 2.0).cos();
+        let y = lat2 - lat1;
+        T::from_f64(x.hypot(y) * self.units.radius())
+    }
+    fn unit(&self) -> Option<DistanceUnit> {
+        Some(self.units.unit)
+    }
+    fn max_latitude_gap(&self, distance: T) -> Option<f64> {
+        Some((distance.to_f64() / self.units.radius()).to_degrees())
+    }
+}
+
+/// geodesic distance on the WGS84 ellipsoid using Vincenty's inverse formula
+///
+/// accurate to well under a millimetre, but the iteration does not converge for nearly
+/// antipodal points, in which case the haversine distance on the WGS84 mean radius is used.
+/// the ellipsoid is fixed so only the unit is configurable
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vincenty {
+    pub unit: DistanceUnit,
+}
+
+impl Vincenty {
+    pub fn new(unit: DistanceUnit) -> Self {
+        Self { unit }
+    }
+
+    /// geodesic distance in meters, None when the iteration fails to converge
+    fn inverse_meters(coord1: (f64, f64), coord2: (f64, f64)) -> Option<f64> {
+        let b = (1.0 - WGS84_F) * WGS84_A;
+        let l = (coord2.1 - coord1.1).to_radians();
+        let u1 = ((1.0 - WGS84_F) * coord1.0.to_radians().tan()).atan();
+        let u2 = ((1.0 - WGS84_F) * coord2.0.to_radians().tan()).atan();
+        let (sin_u1, cos_u1) = u1.sin_cos();
+        let (sin_u2, cos_u2) = u2.sin_cos();
+
+        let mut lambda = l;
+        for _ in 0..VINCENTY_MAX_ITERATIONS {
+            let (sin_lambda, cos_lambda) = lambda.sin_cos();
+            let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+                + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+            .sqrt();
+            if sin_sigma == 0.0 {
+                // coincident points
+                return Some(0.0);
+            }
+            let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+            let sigma = sin_sigma.atan2(cos_sigma);
+            let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+            let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+            // equatorial line has cos_sq_alpha == 0
+            let cos_2sigma_m = if cos_sq_alpha != 0.0 {
+                cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+            } else {
+                0.0
+            };
+            let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+            let previous_lambda = lambda;
+            lambda = l
+                + (1.0 - c)
+                    * WGS84_F
+                    * sin_alpha
+                    * (sigma
+                        + c * sin_sigma
+                            * (cos_2sigma_m
+                                + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+
+            if (lambda - previous_lambda).abs() < 1e-12 {
+                let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - b * b) / (b * b);
+                let big_a = 1.0
+                    + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+                let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+                let delta_sigma = big_b
+                    * sin_sigma
+                    * (cos_2sigma_m
+                        + big_b / 4.0
+                            * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+                                - big_b / 6.0
+                                    * cos_2sigma_m
+                                    * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                                    * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)));
+                return Some(b * big_a * (sigma - delta_sigma));
+            }
+        }
+        None
+    }
+}
+
+impl<T: LatLngType> DistanceMetric<T> for Vincenty {
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T {
+        let coord1 = (coord1.0.to_f64(), coord1.1.to_f64());
+        let coord2 = (coord2.0.to_f64(), coord2.1.to_f64());
+        let meters = Self::inverse_meters(coord1, coord2).unwrap_or_else(|| {
+            f64::haversine_with_radius(&coord1, &coord2, WGS84_MEAN_RADIUS_METERS)
+        });
+        T::from_f64(DistanceUnit::Meters.convert(meters, self.unit))
+    }
+    fn unit(&self) -> Option<DistanceUnit> {
+        Some(self.unit)
+    }
+}
+
+/// squared euclidean distance for data that is already projected onto a plane
+/// (UTM, web mercator meters, ...), the coordinates are used as is
+///
+/// pair it with [crate::nodes::CentroidMethod::Planar], the spherical centroid
+/// only makes sense for lat/lng
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SquaredEuclidean;
+
+impl<T: LatLngType> DistanceMetric<T> for SquaredEuclidean {
+    fn distance(&self, coord1: &(T, T), coord2: &(T, T)) -> T {
+        let d_x = coord1.0 - coord2.0;
+        let d_y = coord1.1 - coord2.1;
+        d_x * d_x + d_y * d_y
+    }
+    fn expects_lat_lng(&self) -> bool {
+        false
+    }
+}
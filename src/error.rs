@@ -0,0 +1,138 @@
+use std::fmt::{Display, Formatter};
+
+/// reasons a clustering run can be rejected
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClusterError {
+    /// k must be at least 1
+    ZeroClusters,
+    /// at least one round has to run
+    ZeroIterations,
+    /// at least one initialization, or CLARA sample, has to run
+    ZeroRestarts,
+    /// a mini-batch run needs at least one point per batch
+    ZeroBatchSize,
+    /// the decay of an online clusterer has to be in (0, 1]
+    InvalidDecay { decay: f64 },
+    /// an online clusterer can only swap its metric before it absorbed any point
+    MetricAfterUpdate { points_seen: usize },
+    /// the neighbourhood radius has to be positive and finite, and when extracted from
+    /// OPTICS no larger than its max_eps
+    InvalidEps { eps: f64 },
+    /// the xi steepness of OPTICS has to be in (0, 1)
+    InvalidXi { xi: f64 },
+    /// a density based run needs at least one point per neighbourhood
+    ZeroMinPoints,
+    /// a cluster needs at least two data points
+    InvalidMinClusterSize { min_cluster_size: usize },
+    /// a dendrogram can only be cut at a distance of zero or more
+    InvalidThreshold { threshold: f64 },
+    /// the range of k to try holds no k
+    EmptyKRange { min_k: usize, max_k: usize },
+    /// a quality score comparing clusters needs at least two of them and fewer than
+    /// there are data points
+    InvalidClusterCount { clusters: usize, n: usize },
+    /// a node's capacity needs a finite min of 0 or more and a max no smaller than it,
+    /// counts are rounded to whole data points first
+    InvalidCapacity { node: usize, min: f64, max: f64 },
+    /// capacities were given for a different number of nodes than k
+    CapacityCount { k: usize, found: usize },
+    /// no data points were provided
+    EmptyInput,
+    /// more clusters were requested than there are data points
+    TooManyClusters { k: usize, n: usize },
+    /// the data point at `index` has a NaN or infinite coordinate
+    NonFiniteCoordinate { index: usize },
+    /// the data point at `index` has a latitude outside [-90, 90]
+    /// or a longitude outside [-180, 180]
+    CoordinateOutOfRange { index: usize, lat: f64, lng: f64 },
+    /// the data point at `index` has a weight that is NaN, infinite or not above 0
+    InvalidWeight { index: usize, weight: f64 },
+    /// a result was applied to a different number of data points than it was fitted on
+    LengthMismatch { expected: usize, found: usize },
+    /// the data points do not fit the capacities, their total count or weight has to be
+    /// between the summed min and the summed max capacity of the nodes
+    InfeasibleCapacity {
+        total: f64,
+        min_total: f64,
+        max_total: f64,
+    },
+}
+
+impl Display for ClusterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClusterError::ZeroClusters => write!(f, "k must be at least 1"),
+            ClusterError::ZeroIterations => write!(f, "at least one iteration is required"),
+            ClusterError::ZeroRestarts => write!(f, "at least one initialization is required"),
+            ClusterError::ZeroBatchSize => write!(f, "batch size must be at least 1"),
+            ClusterError::InvalidDecay { decay } => {
+                write!(f, "decay must be in (0, 1], got {decay}")
+            }
+            ClusterError::MetricAfterUpdate { points_seen } => {
+                write!(
+                    f,
+                    "the metric can't change after {points_seen} points were absorbed"
+                )
+            }
+            ClusterError::InvalidEps { eps } => {
+                write!(f, "eps must be positive and finite, got {eps}")
+            }
+            ClusterError::InvalidXi { xi } => write!(f, "xi must be in (0, 1), got {xi}"),
+            ClusterError::ZeroMinPoints => write!(f, "min_points must be at least 1"),
+            ClusterError::InvalidMinClusterSize { min_cluster_size } => {
+                write!(
+                    f,
+                    "min_cluster_size must be at least 2, got {min_cluster_size}"
+                )
+            }
+            ClusterError::InvalidThreshold { threshold } => {
+                write!(f, "threshold must be zero or more, got {threshold}")
+            }
+            ClusterError::EmptyKRange { min_k, max_k } => {
+                write!(f, "no k in the range {min_k}..={max_k}")
+            }
+            ClusterError::InvalidClusterCount { clusters, n } => write!(
+                f,
+                "{clusters} clusters of {n} data points, expected between 2 and {}",
+                n.saturating_sub(1)
+            ),
+            ClusterError::InvalidCapacity { node, min, max } => {
+                write!(f, "node {node} has an invalid capacity of {min} to {max}")
+            }
+            ClusterError::CapacityCount { k, found } => {
+                write!(
+                    f,
+                    "expected a capacity for each of the {k} nodes, got {found}"
+                )
+            }
+            ClusterError::EmptyInput => write!(f, "no data points to cluster"),
+            ClusterError::TooManyClusters { k, n } => {
+                write!(f, "cannot build {k} clusters from {n} data points")
+            }
+            ClusterError::NonFiniteCoordinate { index } => {
+                write!(f, "data point {index} has a NaN or infinite coordinate")
+            }
+            ClusterError::CoordinateOutOfRange { index, lat, lng } => write!(
+                f,
+                "data point {index} has out of range coordinates ({lat}, {lng})"
+            ),
+            ClusterError::InvalidWeight { index, weight } => write!(
+                f,
+                "data point {index} has weight {weight}, expected a finite weight above 0"
+            ),
+            ClusterError::LengthMismatch { expected, found } => {
+                write!(f, "expected {expected} data points but got {found}")
+            }
+            ClusterError::InfeasibleCapacity {
+                total,
+                min_total,
+                max_total,
+            } => write!(
+                f,
+                "a total of {total} does not fit capacities summing to {min_total} to {max_total}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClusterError {}
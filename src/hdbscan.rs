@@ -0,0 +1,420 @@
+use crate::density::{core_distance, density_clusters, DensityClusters};
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::nodes::CentroidMethod;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+
+/// distances below this are raised to it before taking 1 / distance, so duplicate points
+/// give a large but finite lambda
+const MIN_LAMBDA_DISTANCE: f64 = 1e-9;
+
+/// builder for HDBSCAN, density based clusters that may each have a different density
+///
+/// the core distance of a data point is the distance within which its nearest data points
+/// (itself included) weigh `min_samples`, the `min_samples`th nearest one at the default
+/// weight of 1. the minimum spanning tree over the mutual reachability distance
+/// max(core a, core b, distance a b) is cut at every length to give a hierarchy, which is
+/// condensed by dropping the splits that shed fewer than `min_cluster_size` points,
+/// counted regardless of weight. the clusters that persist the longest (highest
+/// stability) are kept.
+///
+/// every pair of data points is measured twice, so it is quadratic in time (but not
+/// memory) in the number of data points
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{Haversine, Units};
+/// use coordinate_cluster::hdbscan::Hdbscan;
+///
+/// // a dense downtown, a sparse suburb and a lone point
+/// let mut pings = vec![];
+/// for i in 0..10 {
+///     pings.push((40.7128 + i as f64 * 0.0001, -74.0060));
+///     pings.push((40.9000 + i as f64 * 0.005, -73.8000));
+/// }
+/// pings.push((42.0, -70.0));
+/// let fitted = Hdbscan::new(5)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(fitted.cluster_count, 2);
+/// assert_ne!(fitted.labels[0], fitted.labels[1]);
+/// assert_eq!(fitted.labels[20], None);
+/// assert!(fitted.outlier_scores[20] > fitted.outlier_scores[0]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Hdbscan<M = Haversine> {
+    min_cluster_size: usize,
+    min_samples: Option<usize>,
+    centroid: Option<CentroidMethod>,
+    metric: M,
+}
+
+impl Hdbscan<Haversine> {
+    /// smallest group of data points that counts as a cluster, at least 2
+    pub fn new(min_cluster_size: usize) -> Self {
+        Self {
+            min_cluster_size,
+            min_samples: None,
+            centroid: None,
+            metric: Haversine::default(),
+        }
+    }
+}
+
+impl<M> Hdbscan<M> {
+    /// neighbourhood size behind the core distance, defaults to `min_cluster_size`.
+    /// larger values make the clustering more conservative and mark more noise
+    pub fn min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = Some(min_samples);
+        self
+    }
+
+    /// how the node locations of the clusters are found, defaults to
+    /// [CentroidMethod::Spherical] for lat/lng metrics and [CentroidMethod::Planar] otherwise
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = Some(centroid);
+        self
+    }
+
+    pub fn metric<M2>(self, metric: M2) -> Hdbscan<M2> {
+        Hdbscan {
+            min_cluster_size: self.min_cluster_size,
+            min_samples: self.min_samples,
+            centroid: self.centroid,
+            metric,
+        }
+    }
+
+    pub fn fit<T, DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedHdbscan<T, M>, ClusterError>
+    where
+        T: LatLngType,
+        DATAPOINT: UserDataType<T>,
+        M: DistanceMetric<T> + Clone,
+    {
+        if self.min_cluster_size < 2 {
+            return Err(ClusterError::InvalidMinClusterSize {
+                min_cluster_size: self.min_cluster_size,
+            });
+        }
+        let min_samples = self.min_samples.unwrap_or(self.min_cluster_size);
+        if min_samples == 0 {
+            return Err(ClusterError::ZeroMinPoints);
+        }
+        validate_data_points(1, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let n = coords.len();
+        let distance = |a: usize, b: usize| self.metric.distance(&coords[a], &coords[b]).to_f64();
+
+        let core_distances: Vec<f64> = (0..n)
+            .map(|point| {
+                let mut distances: Vec<(usize, f64)> = (0..n)
+                    .map(|other| (other, distance(point, other)))
+                    .collect();
+                // all of the data weighing less than min_samples falls back to the farthest
+                core_distance(&mut distances, &weights, min_samples as f64)
+                    .unwrap_or_else(|| distances[n - 1].1)
+            })
+            .collect();
+        let tree = SingleLinkage::new(&minimum_spanning_tree(n, &core_distances, distance));
+        let condensed = CondensedTree::new(&tree, self.min_cluster_size);
+        let selected = condensed.select();
+
+        // nearest selected ancestor of every condensed cluster, parents have lower ids
+        let mut label_of_cluster: Vec<Option<usize>> = vec![None; condensed.parents.len()];
+        let mut cluster_stabilities = vec![];
+        for cluster in 0..condensed.parents.len() {
+            label_of_cluster[cluster] = if selected[cluster] {
+                cluster_stabilities.push(condensed.stabilities[cluster]);
+                Some(cluster_stabilities.len() - 1)
+            } else {
+                condensed.parents[cluster].and_then(|parent| label_of_cluster[parent])
+            };
+        }
+        let selected_of_label: Vec<usize> = (0..selected.len()).filter(|&c| selected[c]).collect();
+
+        let mut labels = vec![None; n];
+        let mut probabilities = vec![0.0; n];
+        let mut outlier_scores = vec![0.0; n];
+        for point in 0..n {
+            let (cluster, lambda) = condensed.points[point];
+            let death = condensed.deaths[cluster];
+            if death > 0.0 {
+                outlier_scores[point] = (death - lambda) / death;
+            }
+            labels[point] = label_of_cluster[cluster];
+            if let Some(label) = labels[point] {
+                let death = condensed.deaths[selected_of_label[label]];
+                probabilities[point] = if death > 0.0 {
+                    lambda.min(death) / death
+                } else {
+                    1.0
+                };
+            }
+        }
+        Ok(FittedHdbscan {
+            labels,
+            probabilities,
+            outlier_scores,
+            core_distances: core_distances.into_iter().map(T::from_f64).collect(),
+            cluster_count: cluster_stabilities.len(),
+            cluster_stabilities,
+            centroid: self
+                .centroid
+                .unwrap_or_else(|| CentroidMethod::for_metric(&self.metric)),
+            metric: self.metric.clone(),
+        })
+    }
+}
+
+/// result of [Hdbscan::fit]
+#[derive(Clone, Debug)]
+pub struct FittedHdbscan<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// cluster of each data point in input order, None for noise
+    pub labels: Vec<Option<usize>>,
+    /// how strongly each data point belongs to its cluster, 1 for the points that stay
+    /// until the cluster vanishes and 0 for noise
+    pub probabilities: Vec<f64>,
+    /// GLOSH outlier score of each data point, 0 for points in the densest part of
+    /// their region and towards 1 for points that leave it early
+    pub outlier_scores: Vec<f64>,
+    /// distance of each data point to its `min_samples`th nearest data point
+    pub core_distances: Vec<T>,
+    pub cluster_count: usize,
+    /// stability of each cluster, the sum over its points of how long (in 1 / distance)
+    /// they stayed in it
+    pub cluster_stabilities: Vec<f64>,
+    /// how the node locations of the clusters are found
+    pub centroid: CentroidMethod,
+    pub metric: M,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> FittedHdbscan<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.metric.unit()
+    }
+
+    /// build the nodes and the noise set over the data points that were fitted
+    pub fn clusters<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<DensityClusters<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        density_clusters(
+            &self.labels,
+            self.cluster_count,
+            data_points,
+            &self.metric,
+            self.centroid,
+        )
+    }
+}
+
+fn lambda(distance: f64) -> f64 {
+    1.0 / distance.max(MIN_LAMBDA_DISTANCE)
+}
+
+/// Prim's algorithm over the mutual reachability distance, (a, b, length) per edge
+fn minimum_spanning_tree<F: Fn(usize, usize) -> f64>(
+    n: usize,
+    core_distances: &[f64],
+    distance: F,
+) -> Vec<(usize, usize, f64)> {
+    let mut edges = Vec::with_capacity(n.saturating_sub(1));
+    let mut in_tree = vec![false; n];
+    let mut best = vec![(f64::INFINITY, 0); n];
+    let mut current = 0;
+    for _ in 1..n {
+        in_tree[current] = true;
+        let mut next = (f64::INFINITY, usize::MAX);
+        for other in 0..n {
+            if in_tree[other] {
+                continue;
+            }
+            let reachability = distance(current, other)
+                .max(core_distances[current])
+                .max(core_distances[other]);
+            if reachability < best[other].0 {
+                best[other] = (reachability, current);
+            }
+            if best[other].0 < next.0 || next.1 == usize::MAX {
+                next = (best[other].0, other);
+            }
+        }
+        edges.push((best[next.1].1, next.1, next.0));
+        current = next.1;
+    }
+    edges
+}
+
+/// the single linkage hierarchy, internal node `n + i` merges `children[i]`
+struct SingleLinkage {
+    n: usize,
+    children: Vec<(usize, usize)>,
+    distances: Vec<f64>,
+    sizes: Vec<usize>,
+}
+
+impl SingleLinkage {
+    fn new(edges: &[(usize, usize, f64)]) -> Self {
+        let n = edges.len() + 1;
+        let mut sorted = edges.to_vec();
+        sorted.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+        let mut parent: Vec<usize> = (0..n).collect();
+        let mut node_of_root: Vec<usize> = (0..n).collect();
+        fn find(parent: &mut [usize], mut x: usize) -> usize {
+            while parent[x] != x {
+                parent[x] = parent[parent[x]];
+                x = parent[x];
+            }
+            x
+        }
+        let mut tree = Self {
+            n,
+            children: Vec::with_capacity(n - 1),
+            distances: Vec::with_capacity(n - 1),
+            sizes: Vec::with_capacity(n - 1),
+        };
+        for (a, b, distance) in sorted {
+            let (root_a, root_b) = (find(&mut parent, a), find(&mut parent, b));
+            let (left, right) = (node_of_root[root_a], node_of_root[root_b]);
+            tree.sizes.push(tree.size(left) + tree.size(right));
+            tree.children.push((left, right));
+            tree.distances.push(distance);
+            parent[root_b] = root_a;
+            node_of_root[root_a] = n + tree.children.len() - 1;
+        }
+        tree
+    }
+
+    fn size(&self, node: usize) -> usize {
+        if node < self.n {
+            1
+        } else {
+            self.sizes[node - self.n]
+        }
+    }
+
+    fn root(&self) -> usize {
+        self.n + self.children.len() - 1
+    }
+
+    /// data points below `node`
+    fn leaves(&self, node: usize, out: &mut Vec<usize>) {
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if node < self.n {
+                out.push(node);
+            } else {
+                let (left, right) = self.children[node - self.n];
+                stack.push(left);
+                stack.push(right);
+            }
+        }
+    }
+}
+
+/// the hierarchy with every split that sheds fewer than `min_cluster_size` points
+/// turned into those points falling out of the cluster, cluster 0 is the root and every
+/// cluster has a higher id than its parent
+struct CondensedTree {
+    parents: Vec<Option<usize>>,
+    /// lambda at which each cluster split off its parent
+    births: Vec<f64>,
+    /// highest lambda of any point in the cluster or below it
+    deaths: Vec<f64>,
+    stabilities: Vec<f64>,
+    children: Vec<Vec<usize>>,
+    /// cluster each data point falls out of and the lambda it falls out at
+    points: Vec<(usize, f64)>,
+}
+
+impl CondensedTree {
+    fn new(tree: &SingleLinkage, min_cluster_size: usize) -> Self {
+        let mut condensed = Self {
+            parents: vec![None],
+            births: vec![0.0],
+            deaths: vec![0.0],
+            stabilities: vec![0.0],
+            children: vec![vec![]],
+            points: vec![(0, 0.0); tree.n],
+        };
+        if tree.n == 1 {
+            return condensed;
+        }
+        let mut leaves = vec![];
+        let mut stack = vec![(tree.root(), 0)];
+        while let Some((node, cluster)) = stack.pop() {
+            let (left, right) = tree.children[node - tree.n];
+            let lambda = lambda(tree.distances[node - tree.n]);
+            let big_left = tree.size(left) >= min_cluster_size;
+            let big_right = tree.size(right) >= min_cluster_size;
+            if big_left && big_right {
+                for child in [left, right] {
+                    let id = condensed.parents.len();
+                    condensed.parents.push(Some(cluster));
+                    condensed.births.push(lambda);
+                    condensed.deaths.push(0.0);
+                    condensed.stabilities.push(0.0);
+                    condensed.children.push(vec![]);
+                    condensed.children[cluster].push(id);
+                    condensed.stabilities[cluster] +=
+                        tree.size(child) as f64 * (lambda - condensed.births[cluster]);
+                    stack.push((child, id));
+                }
+                continue;
+            }
+            for (child, big) in [(left, big_left), (right, big_right)] {
+                if big {
+                    stack.push((child, cluster));
+                    continue;
+                }
+                leaves.clear();
+                tree.leaves(child, &mut leaves);
+                for &point in leaves.iter() {
+                    condensed.points[point] = (cluster, lambda);
+                    condensed.stabilities[cluster] += lambda - condensed.births[cluster];
+                    condensed.deaths[cluster] = condensed.deaths[cluster].max(lambda);
+                }
+            }
+        }
+        for cluster in (1..condensed.parents.len()).rev() {
+            let parent = condensed.parents[cluster].expect("only the root has no parent");
+            condensed.deaths[parent] = condensed.deaths[parent].max(condensed.deaths[cluster]);
+        }
+        condensed
+    }
+
+    /// excess of mass selection, a cluster is kept when it is at least as stable as the
+    /// best selection among its descendants. the root is never selected
+    fn select(&self) -> Vec<bool> {
+        let count = self.parents.len();
+        let mut selected = vec![false; count];
+        let mut best = self.stabilities.clone();
+        for cluster in (1..count).rev() {
+            let below: f64 = self.children[cluster]
+                .iter()
+                .map(|&child| best[child])
+                .sum();
+            if self.stabilities[cluster] >= below {
+                selected[cluster] = true;
+                let mut stack = self.children[cluster].clone();
+                while let Some(descendant) = stack.pop() {
+                    selected[descendant] = false;
+                    stack.extend(self.children[descendant].iter().copied());
+                }
+            } else {
+                best[cluster] = below;
+            }
+        }
+        selected
+    }
+}
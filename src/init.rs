@@ -0,0 +1,218 @@
+use crate::distance::DistanceMetric;
+use crate::user_data::{LatLngType, UserDataType};
+
+/// strategy used to place the first set of centroids before the first round
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InitStrategy {
+    /// uniformly random centroids inside the lat/lng bounding box of the data
+    Random,
+    /// k-means++ sampling by weight * D² on the configured distance metric
+    #[default]
+    KMeansPlusPlus,
+    /// k-means|| (scalable k-means++), samples roughly `oversampling * k` candidates
+    /// per round independently for every point, then reduces the weighted
+    /// candidates down to k centroids with k-means++
+    KMeansParallel { oversampling: f64, rounds: usize },
+}
+
+impl InitStrategy {
+    /// k-means|| with the oversampling factor and round count suggested in the paper
+    pub fn kmeans_parallel() -> Self {
+        InitStrategy::KMeansParallel {
+            oversampling: 2.0,
+            rounds: 5,
+        }
+    }
+
+    /// returns exactly k starting centroids for the data points, heavier data points are
+    /// more likely to be picked by the k-means++ and k-means|| strategies
+    ///
+    /// every random decision is drawn from `rng`, so a seeded generator gives the same
+    /// centroids for the same input
+    pub fn initial_centroids<T, DATAPOINT, M>(
+        &self,
+        k: usize,
+        data_points: &[DATAPOINT],
+        metric: &M,
+        rng: &mut fastrand::Rng,
+    ) -> Vec<(T, T)>
+    where
+        T: LatLngType,
+        DATAPOINT: UserDataType<T>,
+        M: DistanceMetric<T>,
+    {
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        self.weighted_centroids(k, &coords, &weights, metric, rng)
+    }
+
+    /// [InitStrategy::initial_centroids] on coordinates and their weights
+    pub(crate) fn weighted_centroids<T, M>(
+        &self,
+        k: usize,
+        coords: &[(T, T)],
+        weights: &[f64],
+        metric: &M,
+        rng: &mut fastrand::Rng,
+    ) -> Vec<(T, T)>
+    where
+        T: LatLngType,
+        M: DistanceMetric<T>,
+    {
+        if coords.is_empty() || k == 0 {
+            return random_in_bounding_box(k, coords, rng);
+        }
+        match *self {
+            InitStrategy::Random => random_in_bounding_box(k, coords, rng),
+            InitStrategy::KMeansPlusPlus => kmeans_plus_plus(k, coords, weights, metric, rng),
+            InitStrategy::KMeansParallel {
+                oversampling,
+                rounds,
+            } => kmeans_parallel(k, coords, weights, oversampling, rounds, metric, rng),
+        }
+    }
+}
+
+/// drop k centroids uniformly at random inside the bounding box of the coords
+pub(crate) fn random_in_bounding_box<T: LatLngType>(
+    k: usize,
+    coords: &[(T, T)],
+    rng: &mut fastrand::Rng,
+) -> Vec<(T, T)> {
+    let bounds = crate::min_max(coords);
+    (0..k)
+        .map(|_| match bounds {
+            Some(((lat_min, lat_max), (lng_min, lng_max))) => (
+                T::rand_with(rng, Some(lat_min), Some(lat_max)),
+                T::rand_with(rng, Some(lng_min), Some(lng_max)),
+            ),
+            None => (T::rand_with(rng, None, None), T::rand_with(rng, None, None)),
+        })
+        .collect()
+}
+
+/// pick an index with probability proportional to its weight
+/// returns None when every weight is zero
+fn sample_weighted(weights: &[f64], rng: &mut fastrand::Rng) -> Option<usize> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+    let target = rng.f64() * total;
+    let mut cumulative = 0.0;
+    let mut last_positive = None;
+    for (i, w) in weights.iter().enumerate() {
+        if *w <= 0.0 {
+            continue;
+        }
+        cumulative += w;
+        last_positive = Some(i);
+        if target < cumulative {
+            return Some(i);
+        }
+    }
+    // floating point rounding can leave target just past the last bucket
+    last_positive
+}
+
+/// squared distance from a coord to its closest centroid
+fn closest_distance_sq<T: LatLngType, M: DistanceMetric<T>>(
+    coord: &(T, T),
+    centroids: &[(T, T)],
+    metric: &M,
+) -> f64 {
+    centroids
+        .iter()
+        .map(|c| metric.distance(c, coord).to_f64().powi(2))
+        .fold(f64::MAX, f64::min)
+}
+
+/// weighted k-means++ seeding, each new centroid is drawn with probability
+/// proportional to weight * D² where D is the distance to the closest centroid
+///
+/// if every remaining point sits on an existing centroid (fewer distinct points than k)
+/// the remaining centroids are drawn from the points by weight alone
+fn kmeans_plus_plus<T: LatLngType, M: DistanceMetric<T>>(
+    k: usize,
+    coords: &[(T, T)],
+    weights: &[f64],
+    metric: &M,
+    rng: &mut fastrand::Rng,
+) -> Vec<(T, T)> {
+    let mut centroids = Vec::with_capacity(k);
+    let first = sample_weighted(weights, rng).unwrap_or_else(|| rng.usize(..coords.len()));
+    centroids.push(coords[first]);
+
+    let mut distances: Vec<f64> = coords
+        .iter()
+        .map(|coord| metric.distance(&coords[first], coord).to_f64().powi(2))
+        .collect();
+
+    while centroids.len() < k {
+        let scores: Vec<f64> = distances.iter().zip(weights).map(|(d, w)| d * w).collect();
+        let next = sample_weighted(&scores, rng)
+            .or_else(|| sample_weighted(weights, rng))
+            .unwrap_or_else(|| rng.usize(..coords.len()));
+        let centroid = coords[next];
+        centroids.push(centroid);
+        for (distance, coord) in distances.iter_mut().zip(coords) {
+            *distance = distance.min(metric.distance(&centroid, coord).to_f64().powi(2));
+        }
+    }
+    centroids
+}
+
+/// k-means|| seeding (Bahmani et al. 2012)
+///
+/// every round samples each point independently with probability
+/// `oversampling * k * weight * D² / cost`, so the sampling step has no sequential
+/// dependency between points. the candidates are weighted by the weight of the points
+/// they are closest to and reduced to k centroids with weighted k-means++
+fn kmeans_parallel<T: LatLngType, M: DistanceMetric<T>>(
+    k: usize,
+    coords: &[(T, T)],
+    weights: &[f64],
+    oversampling: f64,
+    rounds: usize,
+    metric: &M,
+    rng: &mut fastrand::Rng,
+) -> Vec<(T, T)> {
+    let first = sample_weighted(weights, rng).unwrap_or_else(|| rng.usize(..coords.len()));
+    let mut candidates = vec![coords[first]];
+    let mut distances: Vec<f64> = coords
+        .iter()
+        .map(|coord| closest_distance_sq(coord, &candidates, metric))
+        .collect();
+    let expected_per_round = oversampling * k as f64;
+
+    for _ in 0..rounds {
+        let cost: f64 = distances.iter().zip(weights).map(|(d, w)| d * w).sum();
+        if cost <= 0.0 {
+            break;
+        }
+        let sampled: Vec<(T, T)> = coords
+            .iter()
+            .zip(distances.iter().zip(weights))
+            .filter(|(_, (d, w))| rng.f64() < (expected_per_round * *d * *w / cost).min(1.0))
+            .map(|(coord, _)| *coord)
+            .collect();
+        for (distance, coord) in distances.iter_mut().zip(coords) {
+            *distance = distance.min(closest_distance_sq(coord, &sampled, metric));
+        }
+        candidates.extend(sampled);
+    }
+
+    let mut candidate_weights = vec![0.0; candidates.len()];
+    for (coord, weight) in coords.iter().zip(weights) {
+        let mut closest = (0, f64::MAX);
+        for (i, candidate) in candidates.iter().enumerate() {
+            let dist = metric.distance(candidate, coord).to_f64();
+            if dist < closest.1 {
+                closest = (i, dist);
+            }
+        }
+        candidate_weights[closest.0] += weight;
+    }
+
+    kmeans_plus_plus(k, &candidates, &candidate_weights, metric, rng)
+}
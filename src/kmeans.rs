@@ -0,0 +1,641 @@
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::init::{self, InitStrategy};
+use crate::nodes::{CentroidMethod, Node};
+use crate::parallel;
+use crate::triangle::Bounds;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::{validate_data_points, CalcOptions, ConvergenceReport};
+
+/// what happens to a node that ends a round without any children
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EmptyClusterPolicy {
+    /// reseed it uniformly at random inside the bounding box of the surviving centroids
+    #[default]
+    RandomInBounds,
+    /// move it onto the data point with the largest weighted distance to its own node,
+    /// several empty nodes each take a different coordinate
+    FarthestPoint,
+    /// remove it, the result can end up with fewer than k nodes
+    Drop,
+}
+
+/// how each round finds the closest centroid of every data point, every variant gives
+/// the same assignments
+///
+/// the bounded variants rely on the triangle inequality and only kick in for metrics
+/// where [DistanceMetric::satisfies_triangle_inequality] holds, others run [Algorithm::Lloyd]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    /// measure the distance from every data point to every centroid
+    #[default]
+    Lloyd,
+    /// Elkan's bounds, one lower bound per data point and centroid plus the distances
+    /// between centroids. skips the most distance calls but holds n * k bounds
+    Elkan,
+    /// Hamerly's bounds, a single lower bound per data point. less memory than
+    /// [Algorithm::Elkan] and usually the faster of the two for small k
+    Hamerly,
+}
+
+/// builder for a k-means run
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{Haversine, Units};
+/// use coordinate_cluster::user_data::UserDataType;
+/// use coordinate_cluster::KMeans;
+///
+/// #[derive(Clone)]
+/// struct Site {
+///     lat: f64,
+///     lng: f64,
+/// }
+///
+/// impl UserDataType<f64> for Site {
+///     fn get_coords(&self) -> (f64, f64) {
+///         (self.lat, self.lng)
+///     }
+/// }
+///
+/// let sites = [
+///     Site { lat: 40.71, lng: -74.00 },
+///     Site { lat: 40.73, lng: -73.99 },
+///     Site { lat: 51.50, lng: -0.12 },
+///     Site { lat: 51.52, lng: -0.10 },
+/// ];
+/// let fitted = KMeans::new(2)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .seed(7)
+///     .n_init(3)
+///     .fit(&sites)
+///     .unwrap();
+/// assert_eq!(fitted.cluster(&sites).unwrap().nodes.len(), 2);
+/// assert!(fitted.model.inertia < 10.0);
+///
+/// let model = fitted.into_model();
+/// let brooklyn = Site { lat: 40.68, lng: -73.94 };
+/// assert_eq!(model.predict(&brooklyn), model.predict(&sites[0]));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct KMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    k: usize,
+    options: CalcOptions<T, M>,
+}
+
+impl<T: LatLngType> KMeans<T, Haversine> {
+    /// k clusters with the defaults of [CalcOptions]
+    pub fn new(k: usize) -> Self {
+        Self::with_options(k, CalcOptions::default())
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> KMeans<T, M> {
+    pub fn with_options(k: usize, options: CalcOptions<T, M>) -> Self {
+        Self { k, options }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn options(&self) -> &CalcOptions<T, M> {
+        &self.options
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.options.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn tolerance(mut self, tolerance: T) -> Self {
+        self.options.tolerance = tolerance;
+        self
+    }
+
+    pub fn init(mut self, init: InitStrategy) -> Self {
+        self.options.init = init;
+        self
+    }
+
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.options.centroid = centroid;
+        self
+    }
+
+    /// swap the distance metric, the tolerance keeps its value but is now read in the
+    /// units of the new metric
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> KMeans<T, M2> {
+        let options = self.options;
+        KMeans {
+            k: self.k,
+            options: CalcOptions {
+                max_iterations: options.max_iterations,
+                tolerance: options.tolerance,
+                init: options.init,
+                centroid: options.centroid,
+                metric,
+                seed: options.seed,
+                n_init: options.n_init,
+                empty_cluster: options.empty_cluster,
+                algorithm: options.algorithm,
+            },
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    pub fn n_init(mut self, n_init: usize) -> Self {
+        self.options.n_init = n_init;
+        self
+    }
+
+    pub fn empty_cluster_policy(mut self, policy: EmptyClusterPolicy) -> Self {
+        self.options.empty_cluster = policy;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.options.algorithm = algorithm;
+        self
+    }
+
+    /// cluster the data points, the result refers to them by index and only borrows
+    /// them again when materialized with [FittedKMeans::cluster]
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedKMeans<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        fit(self.k, data_points, &self.options)
+    }
+}
+
+/// result of [KMeans::fit], the node of every training data point plus the model
+#[derive(Clone, Debug)]
+pub struct FittedKMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// index of the node each data point was assigned to, in input order
+    pub labels: Vec<usize>,
+    /// distance of each data point to its node's centroid, in input order
+    pub distances: Vec<T>,
+    pub model: KMeansModel<T, M>,
+    /// how the objective varied over the restarts, a single entry for the variants
+    /// that do not restart
+    pub restarts: RestartSpread<T>,
+}
+
+/// inertia of every restart of a run, see [CalcOptions::n_init]. a wide spread means
+/// the initialization matters and more restarts are worth it
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestartSpread<T: LatLngType> {
+    /// in the order the restarts were seeded
+    pub inertias: Vec<T>,
+    /// the restart that was kept, the first one with the lowest inertia
+    pub best: usize,
+}
+
+impl<T: LatLngType> RestartSpread<T> {
+    pub fn min(&self) -> T {
+        self.inertias[self.best]
+    }
+
+    pub fn max(&self) -> T {
+        self.inertias.iter().fold(
+            self.min(),
+            |max, &inertia| if inertia > max { inertia } else { max },
+        )
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.inertias
+            .iter()
+            .map(|inertia| inertia.to_f64())
+            .sum::<f64>()
+            / self.inertias.len() as f64
+    }
+
+    /// population standard deviation of the inertias
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .inertias
+            .iter()
+            .map(|inertia| (inertia.to_f64() - mean).powi(2))
+            .sum::<f64>()
+            / self.inertias.len() as f64;
+        variance.sqrt()
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> FittedKMeans<T, M> {
+    pub fn centroids(&self) -> &[(T, T)] {
+        &self.model.centroids
+    }
+
+    /// drop the per point assignments and keep only the model
+    pub fn into_model(self) -> KMeansModel<T, M> {
+        self.model
+    }
+
+    /// build the node/children view over the data points that were fitted,
+    /// the children borrow from `data_points` rather than cloning them
+    pub fn cluster<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        if data_points.len() != self.labels.len() {
+            return Err(ClusterError::LengthMismatch {
+                expected: self.labels.len(),
+                found: data_points.len(),
+            });
+        }
+        Ok(cluster_from_labels(
+            &self.model,
+            &self.labels,
+            &self.distances,
+            data_points,
+        ))
+    }
+}
+
+/// learned centroids and metadata of a k-means run, holds none of the training data
+#[derive(Clone, Debug)]
+pub struct KMeansModel<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// one centroid per node, in node order
+    pub centroids: Vec<(T, T)>,
+    /// number of training points assigned to each node
+    pub sizes: Vec<usize>,
+    /// sum of the distances of each node's training points to its centroid, each
+    /// multiplied by the point's weight
+    pub total_distances: Vec<T>,
+    /// sum of every node's total distance
+    pub inertia: T,
+    pub report: ConvergenceReport<T>,
+    pub metric: M,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> KMeansModel<T, M> {
+    /// unit of [KMeansModel::inertia] and of the distances returned by predict
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.metric.unit()
+    }
+
+    /// index of the closest centroid
+    pub fn predict<P: UserDataType<T>>(&self, data_point: &P) -> usize {
+        self.predict_with_distance(data_point).0
+    }
+
+    pub fn predict_batch<P: UserDataType<T>>(&self, data_points: &[P]) -> Vec<usize> {
+        data_points
+            .iter()
+            .map(|data_point| self.predict(data_point))
+            .collect()
+    }
+
+    /// index of the closest centroid and the distance to it,
+    /// ties go to the lowest index as in [Cluster::assign_data_point_to_closest_node]
+    pub fn predict_with_distance<P: UserDataType<T>>(&self, data_point: &P) -> (usize, T) {
+        closest_centroid(&data_point.get_coords(), &self.centroids, &self.metric)
+    }
+}
+
+/// index of the closest centroid and the distance to it, ties go to the lowest index
+pub(crate) fn closest_centroid<T, M>(
+    coords: &(T, T),
+    centroids: &[(T, T)],
+    metric: &M,
+) -> (usize, T)
+where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let mut min_loc: (usize, T) = (usize::default(), T::max_value());
+    for (k, centroid) in centroids.iter().enumerate() {
+        let dist = metric.distance(centroid, coords);
+        if dist < min_loc.1 {
+            min_loc = (k, dist);
+        }
+    }
+    min_loc
+}
+
+/// one node per centroid of `model` holding the data points labelled with it, the
+/// lengths have been checked by the caller
+pub(crate) fn cluster_from_labels<'a, T, DATAPOINT, M>(
+    model: &KMeansModel<T, M>,
+    labels: &[usize],
+    distances: &[T],
+    data_points: &'a [DATAPOINT],
+) -> Cluster<T, &'a DATAPOINT>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    let mut nodes: Vec<Node<T, &DATAPOINT>> = model
+        .centroids
+        .iter()
+        .zip(&model.sizes)
+        .map(|(centroid, &size)| Node::new(centroid.0, centroid.1, Vec::with_capacity(size)))
+        .collect();
+    for ((data_point, &label), &distance) in data_points.iter().zip(labels).zip(distances) {
+        nodes[label].push_child(&data_point);
+        nodes[label].total_distance += distance * T::from_f64(data_point.weight());
+    }
+    Cluster {
+        nodes,
+        labels: labels.to_vec(),
+        unit: model.unit(),
+    }
+}
+
+/// assign every coordinate to the given centroids and wrap the result, for the variants
+/// that learn the centroids without a full pass over the data
+pub(crate) fn fitted_from_centroids<T, M>(
+    coords: &[(T, T)],
+    weights: &[f64],
+    centroids: Vec<(T, T)>,
+    metric: &M,
+    report: ConvergenceReport<T>,
+) -> FittedKMeans<T, M>
+where
+    T: LatLngType,
+    M: DistanceMetric<T> + Clone,
+{
+    let mut labels = vec![0; coords.len()];
+    let mut distances = vec![T::default(); coords.len()];
+    parallel::assign(coords, &centroids, metric, &mut labels, &mut distances);
+    let (accumulators, total_distances) =
+        parallel::accumulate(centroids.len(), coords, weights, &labels, &distances);
+    let inertia = total_distances
+        .iter()
+        .fold(T::default(), |acc, &total| acc + total);
+    let model = KMeansModel {
+        centroids,
+        sizes: accumulators.iter().map(|acc| acc.count()).collect(),
+        total_distances,
+        inertia,
+        report,
+        metric: metric.clone(),
+    };
+    FittedKMeans {
+        labels,
+        distances,
+        model,
+        restarts: RestartSpread {
+            inertias: vec![inertia],
+            best: 0,
+        },
+    }
+}
+
+/// run `options.n_init` independent lloyd runs and keep the one with the lowest inertia
+pub(crate) fn fit<DATAPOINT, T, M>(
+    k: usize,
+    data_points: &[DATAPOINT],
+    options: &CalcOptions<T, M>,
+) -> Result<FittedKMeans<T, M>, ClusterError>
+where
+    DATAPOINT: UserDataType<T>,
+    T: LatLngType,
+    M: DistanceMetric<T> + Clone,
+{
+    if options.max_iterations == 0 {
+        return Err(ClusterError::ZeroIterations);
+    }
+    if options.n_init == 0 {
+        return Err(ClusterError::ZeroRestarts);
+    }
+    validate_data_points(k, data_points, &options.metric)?;
+
+    let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+    let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+    let mut rng = options
+        .seed
+        .map(fastrand::Rng::with_seed)
+        .unwrap_or_default();
+    // every restart draws from a seed of its own, so they can run in any order
+    let seeds: Vec<u64> = (0..options.n_init).map(|_| rng.u64(..)).collect();
+    let runs = parallel::restarts(&seeds, |seed| {
+        lloyd(
+            k,
+            &coords,
+            &weights,
+            options,
+            &mut fastrand::Rng::with_seed(seed),
+        )
+    });
+    let inertias: Vec<T> = runs.iter().map(|run| run.inertia).collect();
+    let best = (1..inertias.len()).fold(0, |best, restart| {
+        match inertias[restart] < inertias[best] {
+            true => restart,
+            false => best,
+        }
+    });
+    let run = runs.into_iter().nth(best).expect("n_init is at least 1");
+    Ok(FittedKMeans {
+        labels: run.labels,
+        distances: run.distances,
+        model: KMeansModel {
+            centroids: run.centroids,
+            sizes: run.sizes,
+            total_distances: run.total_distances,
+            inertia: run.inertia,
+            report: run.report,
+            metric: options.metric.clone(),
+        },
+        restarts: RestartSpread { inertias, best },
+    })
+}
+
+/// outcome of a single restart, everything of a [FittedKMeans] but the metric
+pub(crate) struct Run<T: LatLngType> {
+    labels: Vec<usize>,
+    distances: Vec<T>,
+    centroids: Vec<(T, T)>,
+    sizes: Vec<usize>,
+    total_distances: Vec<T>,
+    inertia: T,
+    report: ConvergenceReport<T>,
+}
+
+/// a single lloyd run from freshly initialized centroids, works on the coordinates and a
+/// label per point so nothing is cloned between rounds
+fn lloyd<T, M>(
+    k: usize,
+    coords: &[(T, T)],
+    weights: &[f64],
+    options: &CalcOptions<T, M>,
+    rng: &mut fastrand::Rng,
+) -> Run<T>
+where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let mut centroids = options
+        .init
+        .weighted_centroids(k, coords, weights, &options.metric, rng);
+    let mut labels = vec![0; coords.len()];
+    let mut previous_labels = vec![0; coords.len()];
+    let mut distances = vec![T::default(); coords.len()];
+    // with bounds only the labels are kept up to date, distances are measured on demand
+    let mut bounds = match options.metric.satisfies_triangle_inequality() {
+        true => Bounds::new(options.algorithm, coords.len(), k),
+        false => None,
+    };
+    let mut iteration = 0;
+
+    loop {
+        iteration += 1;
+        previous_labels.copy_from_slice(&labels);
+        match bounds.as_mut() {
+            Some(bounds) => bounds.assign(coords, &centroids, &options.metric, &mut labels),
+            None => parallel::assign(
+                coords,
+                &centroids,
+                &options.metric,
+                &mut labels,
+                &mut distances,
+            ),
+        }
+        let labels_unchanged = iteration > 1 && labels == previous_labels;
+
+        let (accumulators, mut total_distances) =
+            parallel::accumulate(centroids.len(), coords, weights, &labels, &distances);
+
+        let mut count_of_empty_nodes = 0;
+        let mut max_centroid_shift = T::default();
+        let mut next_centroids = Vec::with_capacity(k);
+        let mut shifts = Vec::with_capacity(k);
+        for (location, accumulator) in centroids.iter().zip(&accumulators) {
+            if let Some(centroid) = accumulator.centroid(options.centroid) {
+                let shift = options.metric.distance(location, &centroid);
+                if shift > max_centroid_shift {
+                    max_centroid_shift = shift;
+                }
+                next_centroids.push(centroid);
+                shifts.push(shift);
+            } else {
+                count_of_empty_nodes += 1;
+            }
+        }
+
+        let dropping = options.empty_cluster == EmptyClusterPolicy::Drop;
+        let converged = (count_of_empty_nodes == 0 || dropping)
+            && (labels_unchanged || max_centroid_shift <= options.tolerance);
+        if converged || iteration == options.max_iterations {
+            if bounds.is_some() {
+                parallel::measure(coords, &centroids, &options.metric, &labels, &mut distances);
+                total_distances =
+                    parallel::accumulate(centroids.len(), coords, weights, &labels, &distances).1;
+            }
+            let mut sizes: Vec<usize> = accumulators.iter().map(|acc| acc.count()).collect();
+            if dropping && count_of_empty_nodes > 0 {
+                let kept: Vec<usize> = (0..sizes.len()).filter(|&i| sizes[i] > 0).collect();
+                let mut new_index = vec![usize::MAX; sizes.len()];
+                for (new, &old) in kept.iter().enumerate() {
+                    new_index[old] = new;
+                }
+                for label in labels.iter_mut() {
+                    *label = new_index[*label];
+                }
+                centroids = kept.iter().map(|&i| centroids[i]).collect();
+                total_distances = kept.iter().map(|&i| total_distances[i]).collect();
+                sizes = kept.iter().map(|&i| sizes[i]).collect();
+            }
+            let inertia = total_distances
+                .iter()
+                .fold(T::default(), |acc, &total| acc + total);
+            let report = ConvergenceReport {
+                iterations: iteration,
+                converged,
+                max_centroid_shift,
+            };
+            return Run {
+                labels,
+                distances,
+                centroids,
+                sizes,
+                total_distances,
+                inertia,
+                report,
+            };
+        }
+
+        if count_of_empty_nodes > 0 {
+            match options.empty_cluster {
+                EmptyClusterPolicy::RandomInBounds => {
+                    let refill = init::random_in_bounding_box(
+                        k - next_centroids.len(),
+                        &next_centroids,
+                        rng,
+                    );
+                    next_centroids.extend(refill);
+                }
+                EmptyClusterPolicy::FarthestPoint => {
+                    if bounds.is_some() {
+                        parallel::measure(
+                            coords,
+                            &centroids,
+                            &options.metric,
+                            &labels,
+                            &mut distances,
+                        );
+                    }
+                    next_centroids.extend(farthest_points(
+                        count_of_empty_nodes,
+                        coords,
+                        weights,
+                        &distances,
+                    ));
+                }
+                EmptyClusterPolicy::Drop => {}
+            }
+        }
+        if let Some(bounds) = bounds.as_mut() {
+            match count_of_empty_nodes {
+                0 => bounds.shift(&shifts, &labels),
+                _ => bounds.reset(),
+            }
+        }
+        centroids = next_centroids;
+    }
+}
+
+/// the `count` distinct coordinates with the largest weighted distance to the node they
+/// were assigned to, farthest first. repeats the farthest ones when there are fewer
+/// distinct coordinates than `count`
+pub(crate) fn farthest_points<T: LatLngType>(
+    count: usize,
+    coords: &[(T, T)],
+    weights: &[f64],
+    distances: &[T],
+) -> Vec<(T, T)> {
+    let score = |index: usize| distances[index].to_f64() * weights[index];
+    let mut order: Vec<usize> = (0..coords.len()).collect();
+    order.sort_by(|&a, &b| score(b).total_cmp(&score(a)));
+    let mut chosen: Vec<(T, T)> = Vec::with_capacity(count);
+    for index in order {
+        if chosen.len() == count {
+            break;
+        }
+        if !chosen.contains(&coords[index]) {
+            chosen.push(coords[index]);
+        }
+    }
+    let distinct = chosen.len();
+    for repeat in 0..count - distinct {
+        chosen.push(chosen[repeat % distinct]);
+    }
+    chosen
+}
@@ -0,0 +1,567 @@
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::kmeans::{self, KMeansModel};
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::{validate_data_points, ConvergenceReport};
+
+/// a swap has to lower the total deviation by more than this share of it, keeps rounding
+/// from bouncing between two equally good medoids
+const SWAP_TOLERANCE: f64 = 1e-12;
+
+/// how the medoids are searched for
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MedoidAlgorithm {
+    /// BUILD followed by the classic SWAP, every pass tries every medoid against every
+    /// other data point and applies the single best swap. O(k * n²) per pass
+    Pam,
+    /// BUILD followed by the FasterPAM swap of Schubert and Rousseeuw, a candidate is
+    /// checked against all medoids at once and swapped in as soon as it helps.
+    /// O(n²) per pass and usually far fewer passes, gives the same quality as [MedoidAlgorithm::Pam]
+    #[default]
+    FasterPam,
+    /// CLARA, [MedoidAlgorithm::FasterPam] on `samples` random subsets of the data points
+    /// keeping the medoids with the lowest total deviation over all of them. the best
+    /// medoids so far are carried into every following sample
+    ///
+    /// the other variants hold an n * n distance matrix, this one only a sample_size²
+    /// one and is the variant to use beyond a few thousand data points. `sample_size`
+    /// defaults to 40 + 2k
+    Clara {
+        samples: usize,
+        sample_size: Option<usize>,
+    },
+}
+
+impl MedoidAlgorithm {
+    /// CLARA with the 5 samples of 40 + 2k data points suggested by Kaufman and Rousseeuw
+    pub fn clara() -> Self {
+        MedoidAlgorithm::Clara {
+            samples: 5,
+            sample_size: None,
+        }
+    }
+}
+
+/// builder for k-medoids, clusters whose centers are data points from the input
+///
+/// k-means centroids are averages and can land anywhere, e.g. in a lake. here every node
+/// sits on one of the data points (a store, a depot) chosen to minimize the total
+/// weighted distance of its children to it, which also makes it less sensitive to outliers
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{Haversine, Units};
+/// use coordinate_cluster::kmedoids::KMedoids;
+///
+/// let stores = [
+///     ("Midtown", 40.7549, -73.9840),
+///     ("SoHo", 40.7233, -74.0030),
+///     ("Harlem", 40.8116, -73.9465),
+///     ("Soho", 51.5136, -0.1365),
+///     ("Camden", 51.5390, -0.1426),
+/// ];
+/// let coords: Vec<(f64, f64)> = stores.iter().map(|s| (s.1, s.2)).collect();
+/// let fitted = KMedoids::new(2)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .fit(&coords)
+///     .unwrap();
+/// let depots: Vec<&str> = fitted.medoids.iter().map(|&i| stores[i].0).collect();
+/// assert_eq!(depots, ["Midtown", "Soho"]);
+///
+/// let cluster = fitted.cluster(&coords).unwrap();
+/// assert_eq!(cluster.nodes[0].location, coords[0]);
+/// assert_eq!(fitted.medoid_points(&coords).unwrap()[1], &coords[3]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct KMedoids<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    k: usize,
+    algorithm: MedoidAlgorithm,
+    max_iterations: usize,
+    metric: M,
+    seed: Option<u64>,
+    _coords: std::marker::PhantomData<T>,
+}
+
+impl<T: LatLngType> KMedoids<T, Haversine> {
+    /// k medoids found with [MedoidAlgorithm::FasterPam], at most 100 swap passes
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            algorithm: MedoidAlgorithm::default(),
+            max_iterations: 100,
+            metric: Haversine::default(),
+            seed: None,
+            _coords: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> KMedoids<T, M> {
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn algorithm(mut self, algorithm: MedoidAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// cap on the number of passes over the swap candidates
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> KMedoids<T, M2> {
+        KMedoids {
+            k: self.k,
+            algorithm: self.algorithm,
+            max_iterations: self.max_iterations,
+            metric,
+            seed: self.seed,
+            _coords: std::marker::PhantomData,
+        }
+    }
+
+    /// seed for the samples drawn by [MedoidAlgorithm::Clara], the other variants are
+    /// deterministic
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedKMedoids<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        if self.max_iterations == 0 {
+            return Err(ClusterError::ZeroIterations);
+        }
+        validate_data_points(self.k, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let (medoids, search) = match self.algorithm {
+            MedoidAlgorithm::Pam | MedoidAlgorithm::FasterPam => {
+                let matrix = DistanceMatrix::new(&coords, &weights, &self.metric);
+                let mut medoids = build(&matrix, self.k);
+                let search = match self.algorithm {
+                    MedoidAlgorithm::Pam => pam_swap(&matrix, &mut medoids, self.max_iterations),
+                    _ => faster_pam_swap(&matrix, &mut medoids, self.max_iterations),
+                };
+                (medoids, search)
+            }
+            MedoidAlgorithm::Clara {
+                samples,
+                sample_size,
+            } => self.clara(&coords, &weights, samples, sample_size)?,
+        };
+
+        let centroids: Vec<(T, T)> = medoids.iter().map(|&medoid| coords[medoid]).collect();
+        let shift = |&(from, to): &(usize, usize)| self.metric.distance(&coords[from], &coords[to]);
+        let report = ConvergenceReport {
+            iterations: search.iterations,
+            converged: search.converged,
+            max_centroid_shift: search.last_pass_swaps.iter().map(shift).fold(
+                T::default(),
+                |max, shift| if shift > max { shift } else { max },
+            ),
+        };
+        let fitted =
+            kmeans::fitted_from_centroids(&coords, &weights, centroids, &self.metric, report);
+        Ok(FittedKMedoids {
+            medoids,
+            labels: fitted.labels,
+            distances: fitted.distances,
+            model: fitted.model,
+        })
+    }
+
+    /// the medoids of the sample with the lowest total deviation over every data point
+    fn clara(
+        &self,
+        coords: &[(T, T)],
+        weights: &[f64],
+        samples: usize,
+        sample_size: Option<usize>,
+    ) -> Result<(Vec<usize>, Search), ClusterError> {
+        if samples == 0 {
+            return Err(ClusterError::ZeroRestarts);
+        }
+        let n = coords.len();
+        let sample_size = sample_size.unwrap_or(40 + 2 * self.k).min(n);
+        if sample_size < self.k {
+            return Err(ClusterError::TooManyClusters {
+                k: self.k,
+                n: sample_size,
+            });
+        }
+        let mut rng = self.seed.map(fastrand::Rng::with_seed).unwrap_or_default();
+        let mut pool: Vec<usize> = (0..n).collect();
+        let mut labels = vec![0; n];
+        let mut distances = vec![T::default(); n];
+        let mut best: Option<(f64, Vec<usize>, Search)> = None;
+        for _ in 0..samples {
+            let mut sample = best
+                .as_ref()
+                .map(|(_, medoids, _)| medoids.clone())
+                .unwrap_or_default();
+            rng.shuffle(&mut pool);
+            for &index in pool.iter() {
+                if sample.len() == sample_size {
+                    break;
+                }
+                if !sample.contains(&index) {
+                    sample.push(index);
+                }
+            }
+            let sample_coords: Vec<(T, T)> = sample.iter().map(|&index| coords[index]).collect();
+            let sample_weights: Vec<f64> = sample.iter().map(|&index| weights[index]).collect();
+            let matrix = DistanceMatrix::new(&sample_coords, &sample_weights, &self.metric);
+            let mut medoids = build(&matrix, self.k);
+            let search = faster_pam_swap(&matrix, &mut medoids, self.max_iterations);
+            let medoids: Vec<usize> = medoids.iter().map(|&medoid| sample[medoid]).collect();
+
+            let centroids: Vec<(T, T)> = medoids.iter().map(|&medoid| coords[medoid]).collect();
+            parallel::assign(
+                coords,
+                &centroids,
+                &self.metric,
+                &mut labels,
+                &mut distances,
+            );
+            let deviation: f64 = distances
+                .iter()
+                .zip(weights)
+                .map(|(distance, weight)| distance.to_f64() * weight)
+                .sum();
+            if best.as_ref().is_none_or(|(lowest, ..)| deviation < *lowest) {
+                best = Some((deviation, medoids, search));
+            }
+        }
+        let (_, medoids, search) = best.expect("samples is at least 1");
+        Ok((medoids, search))
+    }
+}
+
+/// result of [KMedoids::fit], the node of every training data point plus the model
+///
+/// the model's centroids are the coordinates of the medoids, so it predicts the closest
+/// medoid like any other k-means model
+#[derive(Clone, Debug)]
+pub struct FittedKMedoids<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// index into the fitted data points of each node's medoid, in node order
+    pub medoids: Vec<usize>,
+    /// index of the node each data point was assigned to, in input order
+    pub labels: Vec<usize>,
+    /// distance of each data point to its node's medoid, in input order
+    pub distances: Vec<T>,
+    /// `inertia` is the total deviation minimized by the swaps, `report.iterations`
+    /// counts swap passes
+    pub model: KMeansModel<T, M>,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> FittedKMedoids<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.model.unit()
+    }
+
+    /// the medoid data point of every node, borrowed from the data points that were fitted
+    pub fn medoid_points<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Vec<&'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        self.check_length(data_points.len())?;
+        Ok(self
+            .medoids
+            .iter()
+            .map(|&medoid| &data_points[medoid])
+            .collect())
+    }
+
+    /// build the node/children view over the data points that were fitted, every node is
+    /// located exactly on its medoid
+    pub fn cluster<'a, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<Cluster<T, &'a DATAPOINT>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        self.check_length(data_points.len())?;
+        Ok(kmeans::cluster_from_labels(
+            &self.model,
+            &self.labels,
+            &self.distances,
+            data_points,
+        ))
+    }
+
+    fn check_length(&self, found: usize) -> Result<(), ClusterError> {
+        if found != self.labels.len() {
+            return Err(ClusterError::LengthMismatch {
+                expected: self.labels.len(),
+                found,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// pairwise distances of the points being searched, plus a finite stand in for the
+/// distance to a missing second medoid when k is 1
+///
+/// column b of row a holds the distance multiplied by the weight of b, so summing a row
+/// gives the weighted deviation of the points from a
+struct DistanceMatrix {
+    n: usize,
+    values: Vec<f64>,
+    ceiling: f64,
+}
+
+impl DistanceMatrix {
+    fn new<T: LatLngType, M: DistanceMetric<T>>(
+        coords: &[(T, T)],
+        weights: &[f64],
+        metric: &M,
+    ) -> Self {
+        let mut values = parallel::distance_matrix(coords, metric);
+        for row in values.chunks_mut(coords.len()) {
+            row.iter_mut()
+                .zip(weights)
+                .for_each(|(value, weight)| *value *= weight);
+        }
+        let ceiling = values.iter().fold(0.0_f64, |max, &d| max.max(d)) * 2.0 + 1.0;
+        Self {
+            n: coords.len(),
+            values,
+            ceiling,
+        }
+    }
+
+    fn row(&self, a: usize) -> &[f64] {
+        &self.values[a * self.n..(a + 1) * self.n]
+    }
+}
+
+/// how the swap phase went
+struct Search {
+    iterations: usize,
+    converged: bool,
+    /// (medoid swapped out, data point swapped in) during the last pass
+    last_pass_swaps: Vec<(usize, usize)>,
+}
+
+/// the greedy BUILD of PAM, starts from the most central point and adds the point that
+/// lowers the total deviation the most until there are k
+fn build(matrix: &DistanceMatrix, k: usize) -> Vec<usize> {
+    let n = matrix.n;
+    let first = (0..n)
+        .map(|i| (i, matrix.row(i).iter().sum::<f64>()))
+        .fold((0, f64::INFINITY), |best, (i, total)| {
+            if total < best.1 {
+                (i, total)
+            } else {
+                best
+            }
+        })
+        .0;
+    let mut medoids = vec![first];
+    let mut is_medoid = vec![false; n];
+    is_medoid[first] = true;
+    let mut nearest = matrix.row(first).to_vec();
+    while medoids.len() < k {
+        let mut best = (usize::MAX, -1.0);
+        for candidate in (0..n).filter(|&c| !is_medoid[c]) {
+            let gain: f64 = matrix
+                .row(candidate)
+                .iter()
+                .zip(&nearest)
+                .map(|(d, near)| (near - d).max(0.0))
+                .sum();
+            if gain > best.1 {
+                best = (candidate, gain);
+            }
+        }
+        let added = best.0;
+        medoids.push(added);
+        is_medoid[added] = true;
+        for (near, &d) in nearest.iter_mut().zip(matrix.row(added)) {
+            *near = near.min(d);
+        }
+    }
+    medoids
+}
+
+/// for every point the slot of its closest medoid, the distance to it and the distance
+/// to the second closest one
+struct Nearest {
+    slot: Vec<usize>,
+    near: Vec<f64>,
+    second: Vec<f64>,
+}
+
+impl Nearest {
+    fn new(matrix: &DistanceMatrix, medoids: &[usize]) -> Self {
+        let n = matrix.n;
+        let mut nearest = Self {
+            slot: vec![0; n],
+            near: vec![matrix.ceiling; n],
+            second: vec![matrix.ceiling; n],
+        };
+        for (slot, &medoid) in medoids.iter().enumerate() {
+            for (point, &d) in matrix.row(medoid).iter().enumerate() {
+                if d < nearest.near[point] {
+                    nearest.second[point] = nearest.near[point];
+                    nearest.near[point] = d;
+                    nearest.slot[point] = slot;
+                } else if d < nearest.second[point] {
+                    nearest.second[point] = d;
+                }
+            }
+        }
+        nearest
+    }
+
+    fn total(&self) -> f64 {
+        self.near.iter().sum()
+    }
+}
+
+/// classic SWAP, applies the best of all k * (n - k) swaps per pass
+fn pam_swap(matrix: &DistanceMatrix, medoids: &mut [usize], max_iterations: usize) -> Search {
+    let n = matrix.n;
+    let mut is_medoid = vec![false; n];
+    medoids.iter().for_each(|&medoid| is_medoid[medoid] = true);
+    let mut nearest = Nearest::new(matrix, medoids);
+    let mut search = Search {
+        iterations: 0,
+        converged: false,
+        last_pass_swaps: vec![],
+    };
+    while search.iterations < max_iterations {
+        search.iterations += 1;
+        search.last_pass_swaps.clear();
+        let threshold = -SWAP_TOLERANCE * nearest.total();
+        let mut best = (0, 0, threshold);
+        for candidate in (0..n).filter(|&c| !is_medoid[c]) {
+            let row = matrix.row(candidate);
+            for slot in 0..medoids.len() {
+                let mut delta = 0.0;
+                for (point, &d) in row.iter().enumerate() {
+                    delta += if nearest.slot[point] == slot {
+                        d.min(nearest.second[point]) - nearest.near[point]
+                    } else {
+                        (d - nearest.near[point]).min(0.0)
+                    };
+                }
+                if delta < best.2 {
+                    best = (slot, candidate, delta);
+                }
+            }
+        }
+        if best.2 >= threshold {
+            search.converged = true;
+            break;
+        }
+        let (slot, candidate, _) = best;
+        search.last_pass_swaps.push((medoids[slot], candidate));
+        is_medoid[medoids[slot]] = false;
+        is_medoid[candidate] = true;
+        medoids[slot] = candidate;
+        nearest = Nearest::new(matrix, medoids);
+    }
+    search
+}
+
+/// FasterPAM's eager swap, the loss of removing each medoid is kept up to date so a
+/// candidate is scored against all of them in a single pass over the points. stops once
+/// n candidates in a row were not worth swapping
+fn faster_pam_swap(
+    matrix: &DistanceMatrix,
+    medoids: &mut [usize],
+    max_iterations: usize,
+) -> Search {
+    let n = matrix.n;
+    let k = medoids.len();
+    let mut is_medoid = vec![false; n];
+    medoids.iter().for_each(|&medoid| is_medoid[medoid] = true);
+    let mut nearest = Nearest::new(matrix, medoids);
+    let mut removal_loss = removal_losses(&nearest, k);
+    let mut search = Search {
+        iterations: 0,
+        converged: false,
+        last_pass_swaps: vec![],
+    };
+    let mut delta = vec![0.0; k];
+    let mut since_swap = 0;
+    let mut candidate = 0;
+    while since_swap < n {
+        if candidate == 0 {
+            if search.iterations == max_iterations {
+                return search;
+            }
+            search.iterations += 1;
+            search.last_pass_swaps.clear();
+        }
+        since_swap += 1;
+        if !is_medoid[candidate] {
+            delta.copy_from_slice(&removal_loss);
+            let mut shared = 0.0;
+            for (point, &d) in matrix.row(candidate).iter().enumerate() {
+                let (slot, near, second) = (
+                    nearest.slot[point],
+                    nearest.near[point],
+                    nearest.second[point],
+                );
+                if d < near {
+                    shared += d - near;
+                    delta[slot] += near - second;
+                } else if d < second {
+                    delta[slot] += d - second;
+                }
+            }
+            let (slot, &lowest) =
+                delta
+                    .iter()
+                    .enumerate()
+                    .fold((0, &f64::INFINITY), |best, current| {
+                        if current.1 < best.1 {
+                            current
+                        } else {
+                            best
+                        }
+                    });
+            if lowest + shared < -SWAP_TOLERANCE * nearest.total() {
+                search.last_pass_swaps.push((medoids[slot], candidate));
+                is_medoid[medoids[slot]] = false;
+                is_medoid[candidate] = true;
+                medoids[slot] = candidate;
+                nearest = Nearest::new(matrix, medoids);
+                removal_loss = removal_losses(&nearest, k);
+                since_swap = 0;
+            }
+        }
+        candidate = (candidate + 1) % n;
+    }
+    search.converged = true;
+    search
+}
+
+/// how much the total deviation grows when each medoid is removed and its points move to
+/// their second closest medoid
+fn removal_losses(nearest: &Nearest, k: usize) -> Vec<f64> {
+    let mut losses = vec![0.0; k];
+    for ((&slot, &near), &second) in nearest.slot.iter().zip(&nearest.near).zip(&nearest.second) {
+        losses[slot] += second - near;
+    }
+    losses
+}
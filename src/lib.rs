@@ -1,9 +1,35 @@
-use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, Haversine};
+use crate::init::InitStrategy;
+use crate::kmeans::EmptyClusterPolicy;
+use crate::nodes::CentroidMethod;
 use crate::user_data::{LatLngType, UserDataType};
 use std::fmt::Debug;
-mod cluster;
+pub mod agglomerative;
+pub mod balanced;
+pub mod cluster;
+pub mod comparison;
+pub mod dbscan;
+pub mod density;
+pub mod distance;
+pub mod error;
+pub mod hdbscan;
+pub mod init;
+pub mod kmeans;
+pub mod kmedoids;
+pub mod metrics;
+pub mod minibatch;
 pub mod user_data;
 pub mod nodes;
+pub mod online;
+pub mod optics;
+pub mod parallel;
+pub mod selection;
+mod triangle;
+
+pub use crate::cluster::Cluster;
+pub use crate::error::ClusterError;
+pub use crate::kmeans::{Algorithm, FittedKMeans, KMeans, KMeansModel};
+pub use crate::nodes::Node;
 
 pub fn min_max<T: LatLngType>(centroids: &[(T, T)]) -> Option<((T, T), (T, T))> {
     centroids.iter().fold(None, |acc, &(lat, lng)| match acc {
@@ -15,47 +41,153 @@ pub fn min_max<T: LatLngType>(centroids: &[(T, T)]) -> Option<((T, T), (T, T))>
     })
 }
 
-pub fn calc<DATAPOINT, T>(k: usize, r: usize, data_points: &[DATAPOINT]) -> Cluster<T, DATAPOINT>
+/// cluster the data points into k nodes over at most r rounds
+///
+/// thin wrapper around [KMeans], use the builder for anything beyond the defaults
+pub fn calc<DATAPOINT, T>(
+    k: usize,
+    r: usize,
+    data_points: &[DATAPOINT],
+) -> Result<Cluster<T, DATAPOINT>, ClusterError>
 where
     DATAPOINT: UserDataType<T> + Clone + Debug,
     T: LatLngType,
 {
-    let mut centroids = vec![];
+    calc_with_init(k, r, data_points, InitStrategy::default())
+}
 
-    for round_trip in 0..r {
-        let mut cluster = Cluster::new(k, data_points, &centroids);
-        let mut count_of_empty_nodes = 0;
-        centroids.truncate(0);
-        for cluster_node_index in 0..k {
-            let node = &mut cluster.nodes[cluster_node_index];
+/// same as [calc] but with an explicit strategy for placing the first centroids
+pub fn calc_with_init<DATAPOINT, T>(
+    k: usize,
+    r: usize,
+    data_points: &[DATAPOINT],
+    init: InitStrategy,
+) -> Result<Cluster<T, DATAPOINT>, ClusterError>
+where
+    DATAPOINT: UserDataType<T> + Clone + Debug,
+    T: LatLngType,
+{
+    let fitted = KMeans::new(k)
+        .max_iterations(r)
+        .init(init)
+        .fit(data_points)?;
+    Ok(fitted.cluster(data_points)?.cloned())
+}
 
-            if !node.children.is_empty() {
-                centroids.push(node.calculate_new_centroid());
-            } else {
-                count_of_empty_nodes += 1;
-            }
+/// settings for [calc_with_options] and [KMeans]
+#[derive(Clone, Copy, Debug)]
+pub struct CalcOptions<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// upper bound on the number of assignment rounds, must be at least 1
+    pub max_iterations: usize,
+    /// stop once no centroid moves further than this between rounds, in the units of `metric`
+    pub tolerance: T,
+    /// how the first centroids are placed
+    pub init: InitStrategy,
+    /// how each node's centroid is recomputed from its children
+    pub centroid: CentroidMethod,
+    /// distance used for assignment, seeding, node total distance and convergence
+    pub metric: M,
+    /// seed for every random decision (seeding, empty node refills), the same seed
+    /// and input always give the same clusters. None draws a fresh seed per run
+    pub seed: Option<u64>,
+    /// number of independently initialized runs, the one with the lowest total
+    /// distance is kept. with the `parallel` feature the runs go in parallel, the spread
+    /// of their totals is in [FittedKMeans::restarts]
+    pub n_init: usize,
+    /// what happens to nodes that end a round without children
+    pub empty_cluster: EmptyClusterPolicy,
+    /// how the closest centroid is found each round
+    pub algorithm: Algorithm,
+}
+
+impl<T: LatLngType, M: DistanceMetric<T> + Default> Default for CalcOptions<T, M> {
+    fn default() -> Self {
+        Self {
+            max_iterations: 100,
+            tolerance: T::default(),
+            init: InitStrategy::default(),
+            centroid: CentroidMethod::default(),
+            metric: M::default(),
+            seed: None,
+            n_init: 1,
+            empty_cluster: EmptyClusterPolicy::default(),
+            algorithm: Algorithm::default(),
         }
-        // if its out last round trip and we have empty nodes, no need filling them
-        if round_trip.lt(&(r - 1)) && count_of_empty_nodes > 0 {
-            let lat_lng_min_max = min_max(&centroids);
-            while centroids.len() < k {
-                if lat_lng_min_max.is_none() {
-                    centroids.push((T::rand(None, None), T::rand(None, None)));
-                } else {
-                    let ((lat_min, lat_max), (lng_min, lng_max)) =
-                        unsafe { lat_lng_min_max.unwrap_unchecked() };
-                    centroids.push((
-                        T::rand(Some(lat_min), Some(lat_max)),
-                        T::rand(Some(lng_min), Some(lng_max)),
-                    ));
-                }
-            }
+    }
+}
+
+/// how a [calc_with_options] or [KMeans::fit] run ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConvergenceReport<T: LatLngType> {
+    /// number of assignment rounds that ran
+    pub iterations: usize,
+    /// true when the run stopped before hitting `max_iterations` because the
+    /// centroids settled or the assignments stopped changing
+    pub converged: bool,
+    /// largest distance any centroid moved in the last round
+    pub max_centroid_shift: T,
+}
+
+/// lloyd iterations that stop as soon as the centroids move less than `options.tolerance`
+/// or no data point changes node, `options.max_iterations` caps the number of rounds
+///
+/// a round with empty nodes never counts as converged unless they are being dropped
+pub fn calc_with_options<DATAPOINT, T, M>(
+    k: usize,
+    data_points: &[DATAPOINT],
+    options: &CalcOptions<T, M>,
+) -> Result<(Cluster<T, DATAPOINT>, ConvergenceReport<T>), ClusterError>
+where
+    DATAPOINT: UserDataType<T> + Clone + Debug,
+    T: LatLngType,
+    M: DistanceMetric<T> + Clone,
+{
+    let fitted = kmeans::fit(k, data_points, options)?;
+    let cluster = fitted.cluster(data_points)?.cloned();
+    Ok((cluster, fitted.model.report))
+}
+
+/// reject inputs that cannot be clustered into k nodes
+///
+/// coordinates must be finite, and when the metric works on lat/lng they must also be in range.
+/// weights must be finite and above 0
+pub(crate) fn validate_data_points<DATAPOINT, T, M>(
+    k: usize,
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<(), ClusterError>
+where
+    DATAPOINT: UserDataType<T>,
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    if k == 0 {
+        return Err(ClusterError::ZeroClusters);
+    }
+    if data_points.is_empty() {
+        return Err(ClusterError::EmptyInput);
+    }
+    if k > data_points.len() {
+        return Err(ClusterError::TooManyClusters {
+            k,
+            n: data_points.len(),
+        });
+    }
+    for (index, data_point) in data_points.iter().enumerate() {
+        let (lat, lng) = data_point.get_coords();
+        let (lat, lng) = (lat.to_f64(), lng.to_f64());
+        if !lat.is_finite() || !lng.is_finite() {
+            return Err(ClusterError::NonFiniteCoordinate { index });
+        }
+        if metric.expects_lat_lng() && (lat.abs() > 90.0 || lng.abs() > 180.0) {
+            return Err(ClusterError::CoordinateOutOfRange { index, lat, lng });
         }
-        if round_trip.eq(&(r - 1)) {
-            return cluster;
+        let weight = data_point.weight();
+        if !(weight.is_finite() && weight > 0.0) {
+            return Err(ClusterError::InvalidWeight { index, weight });
         }
     }
-    unreachable!("Failed to calculate centroids")
+    Ok(())
 }
 
 
@@ -368,8 +500,8 @@ mod tests {
 
     #[test]
     fn test_f64_data() {
-        let cluster1 = super::calc(5, 1, &sample_data::DATASET_F64);
-        let cluster2 = super::calc(10, 10, &sample_data::DATASET_F64);
+        let cluster1 = super::calc(5, 1, &sample_data::DATASET_F64).unwrap();
+        let cluster2 = super::calc(10, 10, &sample_data::DATASET_F64).unwrap();
         let total_distance1 = cluster1
             .nodes
             .iter()
@@ -384,8 +516,8 @@ mod tests {
     #[test]
     fn test_f32_data() {
         for _ in 0..10 {
-            let cluster1 = super::calc(5, 5, &sample_data::DATASET_F32);
-            let cluster2 = super::calc(10, 10, &sample_data::DATASET_F32);
+            let cluster1 = super::calc(5, 5, &sample_data::DATASET_F32).unwrap();
+            let cluster2 = super::calc(10, 10, &sample_data::DATASET_F32).unwrap();
             let total_distance1 = cluster1
                 .nodes
                 .iter()
@@ -397,4 +529,1491 @@ mod tests {
             assert!(total_distance1 >= total_distance2);
         }
     }
+
+    #[test]
+    fn test_init_strategies_return_k_centroids() {
+        use crate::distance::Haversine;
+        use crate::init::InitStrategy;
+        for init in [
+            InitStrategy::Random,
+            InitStrategy::KMeansPlusPlus,
+            InitStrategy::kmeans_parallel(),
+        ] {
+            let centroids: Vec<(f64, f64)> = init.initial_centroids(
+                5,
+                &sample_data::DATASET_F64,
+                &Haversine::default(),
+                &mut fastrand::Rng::with_seed(7),
+            );
+            assert_eq!(centroids.len(), 5);
+            for (lat, lng) in centroids {
+                assert!((-33.9249..=90.0).contains(&lat));
+                assert!((-74.0060..=151.2093).contains(&lng));
+            }
+        }
+    }
+
+    #[test]
+    fn test_kmeans_plus_plus_picks_distinct_sites() {
+        // ten distinct sites, so D² sampling can never pick the same one twice
+        let centroids: Vec<(f64, f64)> = crate::init::InitStrategy::KMeansPlusPlus
+            .initial_centroids(
+                10,
+                &sample_data::DATASET_F64,
+                &crate::distance::Haversine::default(),
+                &mut fastrand::Rng::with_seed(7),
+            );
+        for (i, a) in centroids.iter().enumerate() {
+            for b in centroids.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_calc_with_init() {
+        let cluster = super::calc_with_init(
+            4,
+            5,
+            &sample_data::DATASET_F32,
+            crate::init::InitStrategy::kmeans_parallel(),
+        )
+        .unwrap();
+        assert_eq!(cluster.nodes.len(), 4);
+        let children: usize = cluster.nodes.iter().map(|node| node.children.len()).sum();
+        assert_eq!(children, sample_data::DATASET_F32.len());
+    }
+
+    #[test]
+    fn test_calc_with_options_converges() {
+        let options: super::CalcOptions<f64> = super::CalcOptions {
+            max_iterations: 50,
+            ..Default::default()
+        };
+        // one centroid per distinct site, nothing can move after the first round
+        let (cluster, report) =
+            super::calc_with_options(10, &sample_data::DATASET_F64, &options).unwrap();
+        assert!(report.converged);
+        assert!(report.iterations <= 3);
+        assert_eq!(report.max_centroid_shift, 0.0);
+        assert_eq!(cluster.labels.len(), sample_data::DATASET_F64.len());
+    }
+
+    #[test]
+    fn test_calc_with_options_caps_iterations() {
+        let options: super::CalcOptions<f32> = super::CalcOptions {
+            max_iterations: 1,
+            tolerance: -1.0,
+            ..Default::default()
+        };
+        let (cluster, report) =
+            super::calc_with_options(3, &sample_data::DATASET_F32, &options).unwrap();
+        assert_eq!(report.iterations, 1);
+        assert!(!report.converged);
+        assert_eq!(cluster.nodes.len(), 3);
+    }
+
+    fn node_with(
+        coords: &[(f64, f64)],
+    ) -> crate::nodes::Node<f64, sample_data::ExampleDataPointStructF64> {
+        let children = coords
+            .iter()
+            .map(|&(lat, lng)| sample_data::ExampleDataPointStructF64 {
+                lat,
+                lng,
+                region_id: 0,
+                sub_region_id: 0,
+            })
+            .collect();
+        crate::nodes::Node::new(0.0, 0.0, children)
+    }
+
+    #[test]
+    fn test_spherical_centroid_across_antimeridian() {
+        // Fiji straddles ±180°, the planar mean lands near the prime meridian
+        let node = node_with(&[(-17.0, 179.0), (-17.0, -179.0)]);
+        let (lat, lng) = node
+            .calculate_centroid(crate::nodes::CentroidMethod::Spherical)
+            .unwrap();
+        assert!((lat + 17.0).abs() < 0.01);
+        assert!(lng.abs() > 179.99);
+        let (_, planar_lng) = node.calculate_planar_centroid().unwrap();
+        assert!(planar_lng.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spherical_centroid_near_pole() {
+        let node = node_with(&[(89.0, 0.0), (89.0, 180.0)]);
+        let (lat, _) = node.calculate_spherical_centroid().unwrap();
+        assert!((lat - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spherical_centroid_degenerate_falls_back_to_planar() {
+        let node = node_with(&[(0.0, 0.0), (0.0, 180.0)]);
+        assert_eq!(node.calculate_spherical_centroid(), Some((0.0, 90.0)));
+    }
+
+    #[test]
+    fn test_distance_metrics_agree_on_short_hops() {
+        use crate::distance::{DistanceMetric, Equirectangular, Haversine, Vincenty};
+        let manhattan = (40.7831, -73.9712);
+        let brooklyn = (40.6782, -73.9442);
+        let haversine: f64 = Haversine::default().distance(&manhattan, &brooklyn);
+        let equirectangular: f64 = Equirectangular::default().distance(&manhattan, &brooklyn);
+        let vincenty: f64 = Vincenty::default().distance(&manhattan, &brooklyn);
+        assert!((haversine - equirectangular).abs() / haversine < 0.001);
+        assert!((haversine - vincenty).abs() / haversine < 0.005);
+    }
+
+    #[test]
+    fn test_vincenty_known_distance() {
+        use crate::distance::{DistanceMetric, Vincenty};
+        // JFK to LHR on WGS84 is 5554.3 km
+        let jfk = (40.6413, -73.7781);
+        let lhr = (51.4700, -0.4543);
+        let miles: f64 = Vincenty::default().distance(&jfk, &lhr);
+        assert!((miles * 1.609_344 - 5554.3).abs() < 1.0);
+        // nearly antipodal points do not converge and fall back to the sphere
+        let antipodal: f64 = Vincenty::default().distance(&(0.0, 0.0), &(0.5, 179.7));
+        assert!(antipodal.is_finite() && antipodal > 12000.0);
+    }
+
+    #[test]
+    fn test_calc_with_user_defined_metric() {
+        let metric = |a: &(f64, f64), b: &(f64, f64)| (a.0 - b.0).abs() + (a.1 - b.1).abs();
+        let options = super::CalcOptions {
+            max_iterations: 10,
+            tolerance: 0.0,
+            init: Default::default(),
+            centroid: crate::nodes::CentroidMethod::Planar,
+            metric,
+            seed: None,
+            n_init: 1,
+            empty_cluster: Default::default(),
+            algorithm: Default::default(),
+        };
+        let (cluster, _) =
+            super::calc_with_options(10, &sample_data::DATASET_F64, &options).unwrap();
+        let total: f64 = cluster.nodes.iter().map(|node| node.total_distance).sum();
+        assert!(total < 1e-9);
+    }
+
+    #[test]
+    fn test_units_flow_into_cluster() {
+        use crate::cluster::Cluster;
+        use crate::distance::{DistanceUnit, Haversine, Units};
+        let data = &sample_data::DATASET_F64;
+        let centroids = [(0.0, 0.0)];
+        let mut rng = fastrand::Rng::with_seed(7);
+        let miles = Cluster::new(1, data, &centroids, &Haversine::default(), &mut rng);
+        let nautical_metric = Haversine::new(Units::nautical_miles());
+        let nautical = Cluster::new(1, data, &centroids, &nautical_metric, &mut rng);
+        assert_eq!(miles.unit, Some(DistanceUnit::Miles));
+        assert_eq!(nautical.unit, Some(DistanceUnit::NauticalMiles));
+        let ratio = miles.nodes[0].total_distance / nautical.nodes[0].total_distance;
+        // legacy 3960 mi sphere vs WGS84 mean radius
+        let expected = 3960.0 / (6_371_008.8 / 1852.0);
+        assert!((ratio - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_earth_model_radius() {
+        use crate::distance::{DistanceUnit, EarthModel, Units};
+        assert_eq!(Units::default().radius(), 3960.0);
+        assert!((Units::kilometers().radius() - 6371.0088).abs() < 1e-9);
+        let unit_sphere = EarthModel::Sphere { radius_meters: 1.0 };
+        assert_eq!(Units::new(DistanceUnit::Meters, unit_sphere).radius(), 1.0);
+        let nautical = DistanceUnit::Kilometers.convert(1.852, DistanceUnit::NauticalMiles);
+        assert!((nautical - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_calc_rejects_bad_parameters() {
+        use crate::error::ClusterError;
+        let data = &sample_data::DATASET_F64;
+        assert_eq!(
+            super::calc(0, 5, data).err(),
+            Some(ClusterError::ZeroClusters)
+        );
+        assert_eq!(
+            super::calc(3, 0, data).err(),
+            Some(ClusterError::ZeroIterations)
+        );
+        let empty: [sample_data::ExampleDataPointStructF64; 0] = [];
+        assert_eq!(
+            super::calc(3, 5, &empty).err(),
+            Some(ClusterError::EmptyInput)
+        );
+        assert_eq!(
+            super::calc(60, 5, data).err(),
+            Some(ClusterError::TooManyClusters { k: 60, n: 52 })
+        );
+    }
+
+    #[test]
+    fn test_calc_rejects_bad_coordinates() {
+        use crate::error::ClusterError;
+        let mut data = sample_data::DATASET_F64.to_vec();
+        data[3].lat = f64::NAN;
+        assert_eq!(
+            super::calc(3, 5, &data).err(),
+            Some(ClusterError::NonFiniteCoordinate { index: 3 })
+        );
+        data[3].lat = 35.0;
+        data[7].lng = f64::INFINITY;
+        assert_eq!(
+            super::calc(3, 5, &data).err(),
+            Some(ClusterError::NonFiniteCoordinate { index: 7 })
+        );
+        data[7].lng = 190.0;
+        assert_eq!(
+            super::calc(3, 5, &data).err(),
+            Some(ClusterError::CoordinateOutOfRange {
+                index: 7,
+                lat: data[7].lat,
+                lng: 190.0
+            })
+        );
+        data[7].lng = 0.0;
+        data[9].lat = -91.0;
+        assert!(matches!(
+            super::calc(3, 5, &data),
+            Err(ClusterError::CoordinateOutOfRange { index: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn test_projected_coordinates_skip_range_check() {
+        let data: Vec<_> = sample_data::DATASET_F64
+            .iter()
+            .map(|point| sample_data::ExampleDataPointStructF64 {
+                lat: point.lat * 1000.0,
+                lng: point.lng * 1000.0,
+                ..point.clone()
+            })
+            .collect();
+        let options = super::CalcOptions {
+            max_iterations: 5,
+            tolerance: 0.0,
+            init: Default::default(),
+            centroid: crate::nodes::CentroidMethod::Planar,
+            metric: crate::distance::SquaredEuclidean,
+            seed: None,
+            n_init: 1,
+            empty_cluster: Default::default(),
+            algorithm: Default::default(),
+        };
+        assert!(super::calc_with_options(4, &data, &options).is_ok());
+    }
+
+    #[test]
+    fn test_empty_node_has_no_centroid() {
+        let mut node = node_with(&[]);
+        assert_eq!(node.calculate_new_centroid(), None);
+    }
+
+    #[test]
+    fn test_seeded_runs_are_identical() {
+        for init in [
+            crate::init::InitStrategy::Random,
+            crate::init::InitStrategy::KMeansPlusPlus,
+            crate::init::InitStrategy::kmeans_parallel(),
+        ] {
+            let options: super::CalcOptions<f32> = super::CalcOptions {
+                max_iterations: 10,
+                init,
+                seed: Some(42),
+                ..Default::default()
+            };
+            let (cluster1, report1) =
+                super::calc_with_options(7, &sample_data::DATASET_F32, &options).unwrap();
+            let (cluster2, report2) =
+                super::calc_with_options(7, &sample_data::DATASET_F32, &options).unwrap();
+            assert_eq!(report1, report2);
+            assert_eq!(cluster1.labels, cluster2.labels);
+            for (node1, node2) in cluster1.nodes.iter().zip(&cluster2.nodes) {
+                assert_eq!(node1.location.0.to_bits(), node2.location.0.to_bits());
+                assert_eq!(node1.location.1.to_bits(), node2.location.1.to_bits());
+                assert_eq!(
+                    node1.total_distance.to_bits(),
+                    node2.total_distance.to_bits()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_kmeans_builder_keeps_best_restart() {
+        let single = super::KMeans::new(4)
+            .seed(3)
+            .fit(&sample_data::DATASET_F64)
+            .unwrap();
+        let restarts = super::KMeans::new(4)
+            .seed(3)
+            .n_init(8)
+            .fit(&sample_data::DATASET_F64)
+            .unwrap();
+        // the first restart draws the same centroids as the single run
+        assert!(restarts.model.inertia <= single.model.inertia);
+        assert_eq!(
+            super::KMeans::new(4)
+                .n_init(0)
+                .fit(&sample_data::DATASET_F64)
+                .err(),
+            Some(crate::error::ClusterError::ZeroRestarts)
+        );
+    }
+
+    #[test]
+    fn test_restarts_report_spread() {
+        let fitted = super::KMeans::new(6)
+            .seed(11)
+            .n_init(12)
+            .fit(&sample_data::DATASET_F64)
+            .unwrap();
+        let spread = &fitted.restarts;
+        assert_eq!(spread.inertias.len(), 12);
+        assert_eq!(spread.min(), fitted.model.inertia);
+        assert!(spread
+            .inertias
+            .iter()
+            .all(|&inertia| inertia >= spread.min()));
+        assert!(spread.max() >= spread.min());
+        assert!(spread.mean() >= spread.min() && spread.mean() <= spread.max());
+        assert!(spread.std_dev() >= 0.0);
+        // restarts are seeded up front, so running them in parallel changes nothing
+        let again = super::KMeans::new(6)
+            .seed(11)
+            .n_init(12)
+            .fit(&sample_data::DATASET_F64)
+            .unwrap();
+        assert_eq!(again.restarts, fitted.restarts);
+        assert_eq!(again.labels, fitted.labels);
+        let single = super::KMeans::new(6)
+            .seed(11)
+            .fit(&sample_data::DATASET_F64)
+            .unwrap();
+        assert_eq!(single.restarts.inertias, vec![single.model.inertia]);
+    }
+
+    #[test]
+    fn test_empty_cluster_policies() {
+        use crate::kmeans::EmptyClusterPolicy;
+        for policy in [
+            EmptyClusterPolicy::RandomInBounds,
+            EmptyClusterPolicy::FarthestPoint,
+            EmptyClusterPolicy::Drop,
+        ] {
+            let fitted = super::KMeans::new(9)
+                .init(crate::init::InitStrategy::Random)
+                .empty_cluster_policy(policy)
+                .seed(11)
+                .fit(&sample_data::DATASET_F64)
+                .unwrap();
+            let cluster = fitted.cluster(&sample_data::DATASET_F64).unwrap();
+            for (data_point, &label) in sample_data::DATASET_F64.iter().zip(&cluster.labels) {
+                assert!(cluster.nodes[label]
+                    .children
+                    .iter()
+                    .any(|child| child.region_id == data_point.region_id));
+            }
+            if policy == EmptyClusterPolicy::Drop {
+                assert!(cluster.nodes.iter().all(|node| !node.children.is_empty()));
+            } else {
+                assert_eq!(cluster.nodes.len(), 9);
+            }
+        }
+
+        // reseeded nodes go to different coordinates, ranked by weighted distance
+        let coords = [(0.0, 0.0), (5.0, 5.0), (5.0, 5.0), (3.0, 3.0), (1.0, 1.0)];
+        let weights = [1.0, 1.0, 1.0, 1.0, 10.0];
+        let distances = [0.0, 5.0, 5.0, 3.0, 1.0];
+        assert_eq!(
+            crate::kmeans::farthest_points(3, &coords, &weights, &distances),
+            vec![(1.0, 1.0), (5.0, 5.0), (3.0, 3.0)]
+        );
+        assert_eq!(
+            crate::kmeans::farthest_points(2, &coords[1..3], &weights[1..3], &distances[1..3]),
+            vec![(5.0, 5.0), (5.0, 5.0)]
+        );
+    }
+
+    #[test]
+    fn test_model_predicts_training_labels() {
+        let fitted = super::KMeans::new(6)
+            .seed(5)
+            .fit(&sample_data::DATASET_F32)
+            .unwrap();
+        let labels = fitted.labels.clone();
+        let model = fitted.into_model();
+        assert_eq!(model.predict_batch(&sample_data::DATASET_F32), labels);
+        assert_eq!(
+            model.sizes.iter().sum::<usize>(),
+            sample_data::DATASET_F32.len()
+        );
+
+        let (node, distance) = model.predict_with_distance(&sample_data::TOKYO_F32);
+        assert_eq!(node, model.predict(&sample_data::TOKYO_F32));
+        let expected = crate::user_data::LatLngType::haversine(
+            &model.centroids[node],
+            &(sample_data::TOKYO_F32.lat, sample_data::TOKYO_F32.lng),
+        );
+        assert_eq!(distance, expected);
+        assert_eq!(model.unit(), Some(crate::distance::DistanceUnit::Miles));
+    }
+
+    #[test]
+    fn test_fitted_cluster_borrows_data_points() {
+        let data = &sample_data::DATASET_F64;
+        let fitted = super::KMeans::new(4).seed(2).fit(data).unwrap();
+        assert_eq!(fitted.labels.len(), data.len());
+        assert_eq!(fitted.distances.len(), data.len());
+
+        let cluster = fitted.cluster(data).unwrap();
+        for (index, &label) in fitted.labels.iter().enumerate() {
+            assert!(cluster.nodes[label]
+                .children
+                .iter()
+                .any(|child| std::ptr::eq(*child, &data[index])));
+        }
+        let total: f64 = cluster.nodes.iter().map(|node| node.total_distance).sum();
+        assert!((total - fitted.model.inertia).abs() < 1e-6);
+
+        assert_eq!(
+            fitted.cluster(&data[1..]).err(),
+            Some(crate::error::ClusterError::LengthMismatch {
+                expected: data.len(),
+                found: data.len() - 1
+            })
+        );
+
+        let coords: Vec<(f64, f64)> = data.iter().map(|p| (p.lat, p.lng)).collect();
+        let from_tuples = super::KMeans::new(4).seed(2).fit(&coords).unwrap();
+        assert_eq!(from_tuples.labels, fitted.labels);
+    }
+
+    #[test]
+    fn test_chunked_sums_over_large_input() {
+        // more points than one accumulation chunk, run with and without `--features parallel`
+        let mut rng = fastrand::Rng::with_seed(21);
+        let coords: Vec<(f64, f64)> = (0..5000)
+            .map(|_| (rng.f64() * 120.0 - 60.0, rng.f64() * 360.0 - 180.0))
+            .collect();
+        let kmeans = super::KMeans::new(12).seed(8);
+        let fitted = kmeans.fit(&coords).unwrap();
+        let again = kmeans.fit(&coords).unwrap();
+        assert_eq!(fitted.labels, again.labels);
+        assert_eq!(fitted.model.centroids, again.model.centroids);
+        assert_eq!(fitted.model.inertia, again.model.inertia);
+
+        // the serial and parallel builds agree to the bit, FNV-1a over the labels, the
+        // inertia and the centroids
+        let words = fitted.labels.iter().map(|&label| label as u64).chain(
+            std::iter::once(fitted.model.inertia.to_bits()).chain(
+                fitted
+                    .model
+                    .centroids
+                    .iter()
+                    .flat_map(|&(lat, lng)| [lat.to_bits(), lng.to_bits()]),
+            ),
+        );
+        let hash = words.fold(0xcbf2_9ce4_8422_2325_u64, |hash, word| {
+            (hash ^ word).wrapping_mul(0x0100_0000_01b3)
+        });
+        assert_eq!(hash, 0x8213_4e23_a70d_30e9);
+
+        let cluster = fitted.cluster(&coords).unwrap();
+        for (node, &total) in cluster.nodes.iter().zip(&fitted.model.total_distances) {
+            assert!((node.total_distance - total).abs() < 1e-6 * total.max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_bounded_algorithms_match_lloyd() {
+        use crate::kmeans::{Algorithm, EmptyClusterPolicy};
+        let mut rng = fastrand::Rng::with_seed(4);
+        let coords: Vec<(f64, f64)> = (0..3000)
+            .map(|_| (rng.f64() * 100.0 - 50.0, rng.f64() * 360.0 - 180.0))
+            .collect();
+        for policy in [
+            EmptyClusterPolicy::RandomInBounds,
+            EmptyClusterPolicy::FarthestPoint,
+            EmptyClusterPolicy::Drop,
+        ] {
+            let kmeans = super::KMeans::new(25)
+                .seed(13)
+                .init(crate::init::InitStrategy::Random)
+                .empty_cluster_policy(policy);
+            let lloyd = kmeans.fit(&coords).unwrap();
+            for algorithm in [Algorithm::Elkan, Algorithm::Hamerly] {
+                let bounded = kmeans.algorithm(algorithm).fit(&coords).unwrap();
+                assert_eq!(bounded.labels, lloyd.labels);
+                assert_eq!(bounded.distances, lloyd.distances);
+                assert_eq!(bounded.model.centroids, lloyd.model.centroids);
+                assert_eq!(bounded.model.inertia, lloyd.model.inertia);
+                assert_eq!(bounded.model.report, lloyd.model.report);
+            }
+        }
+
+        let lloyd = super::KMeans::new(7)
+            .seed(3)
+            .fit(&sample_data::DATASET_F32)
+            .unwrap();
+        for algorithm in [Algorithm::Elkan, Algorithm::Hamerly] {
+            let bounded = super::KMeans::new(7)
+                .seed(3)
+                .algorithm(algorithm)
+                .fit(&sample_data::DATASET_F32)
+                .unwrap();
+            assert_eq!(bounded.labels, lloyd.labels);
+            assert_eq!(bounded.model.inertia, lloyd.model.inertia);
+        }
+    }
+
+    #[test]
+    fn test_minibatch_finds_blobs() {
+        use crate::minibatch::MiniBatchKMeans;
+        let centers = [
+            (40.7128, -74.0060),
+            (51.5074, -0.1278),
+            (35.6895, 139.6917),
+            (-33.8688, 151.2093),
+            (-23.5505, -46.6333),
+        ];
+        let mut rng = fastrand::Rng::with_seed(17);
+        let pings: Vec<(f64, f64)> = (0..20_000)
+            .map(|i| {
+                let (lat, lng) = centers[i % centers.len()];
+                (lat + rng.f64() * 0.2 - 0.1, lng + rng.f64() * 0.2 - 0.1)
+            })
+            .collect();
+        let minibatch = MiniBatchKMeans::new(5).batch_size(256).seed(6);
+        let fitted = minibatch.fit(&pings).unwrap();
+        assert_eq!(fitted.model.sizes.iter().sum::<usize>(), pings.len());
+        for center in centers {
+            let (_, miles) = fitted.model.predict_with_distance(&center);
+            assert!(miles < 5.0, "{center:?} is {miles} miles from its centroid");
+        }
+        assert_eq!(
+            minibatch.fit(&pings).unwrap().model.centroids,
+            fitted.model.centroids
+        );
+        assert_eq!(
+            minibatch.batch_size(0).fit(&pings).err(),
+            Some(crate::error::ClusterError::ZeroBatchSize)
+        );
+    }
+
+    #[test]
+    fn test_online_tracks_drift() {
+        use crate::online::OnlineKMeans;
+        let mut online = OnlineKMeans::new(2).decay(0.9);
+        let labels = online
+            .update_batch(&[sample_data::NYC_F64, sample_data::TOKYO_F64])
+            .unwrap();
+        assert_eq!(labels, vec![0, 1]);
+        assert_eq!(
+            online.centroids(),
+            &[
+                (sample_data::NYC_F64.lat, sample_data::NYC_F64.lng),
+                (sample_data::TOKYO_F64.lat, sample_data::TOKYO_F64.lng)
+            ]
+        );
+
+        // the new york centroid walks north with the pings, the tokyo one stays put
+        for step in 1..=200 {
+            let ping = (40.7128 + step as f64 * 0.01, -74.0060);
+            assert_eq!(online.update(&ping).unwrap(), 0);
+        }
+        let (lat, _) = online.centroids()[0];
+        assert!(lat > 42.0, "decayed centroid lagging at {lat}");
+        assert_eq!(online.centroids()[1].0, sample_data::TOKYO_F64.lat);
+        assert_eq!(online.sizes(), &[201, 1]);
+        assert_eq!(online.points_seen(), 202);
+
+        // a bad ping in a batch leaves the state untouched
+        let before = online.centroids().to_vec();
+        assert_eq!(
+            online.update_batch(&[(41.0, -74.0), (f64::NAN, 0.0)]).err(),
+            Some(crate::error::ClusterError::NonFiniteCoordinate { index: 1 })
+        );
+        assert_eq!(online.centroids(), before.as_slice());
+        assert_eq!(
+            online
+                .clone()
+                .metric(crate::distance::SquaredEuclidean)
+                .err(),
+            Some(crate::error::ClusterError::MetricAfterUpdate { points_seen: 202 })
+        );
+
+        let fitted = online.snapshot(&sample_data::DATASET_F64).unwrap();
+        let cluster = fitted.cluster(&sample_data::DATASET_F64).unwrap();
+        assert_eq!(cluster.nodes.len(), 2);
+        assert_eq!(online.model().predict(&sample_data::TOKYO_F64), 1);
+        assert_eq!(
+            OnlineKMeans::<f64>::new(2)
+                .decay(0.0)
+                .update(&(0.0, 0.0))
+                .err(),
+            Some(crate::error::ClusterError::InvalidDecay { decay: 0.0 })
+        );
+    }
+
+    #[test]
+    fn test_dbscan_marks_noise() {
+        use crate::dbscan::Dbscan;
+        use crate::distance::{Haversine, Units};
+        let data = &sample_data::DATASET_F64;
+        // the sample data repeats a handful of sites, clusters are the sites with 4+ copies
+        let fitted = Dbscan::new(1.0, 4)
+            .metric(Haversine::new(Units::kilometers()))
+            .fit(data)
+            .unwrap();
+        let clusters = fitted.clusters(data).unwrap();
+        assert_eq!(clusters.nodes.len(), fitted.cluster_count);
+        for (data_point, label) in data.iter().zip(&fitted.labels) {
+            match label {
+                Some(label) => assert!(clusters.nodes[*label]
+                    .children
+                    .iter()
+                    .any(|child| std::ptr::eq(*child, data_point))),
+                None => assert!(clusters
+                    .noise
+                    .iter()
+                    .any(|noise| std::ptr::eq(*noise, data_point))),
+            }
+        }
+        assert_eq!(
+            clusters.noise.len()
+                + clusters
+                    .nodes
+                    .iter()
+                    .map(|n| n.children.len())
+                    .sum::<usize>(),
+            data.len()
+        );
+        for node in clusters.nodes.iter() {
+            assert!(node.total_distance < 1e-6);
+        }
+
+        // a chain of points 0.9 km apart is one cluster, a stray point is noise
+        let mut chain: Vec<(f64, f64)> = (0..20).map(|i| (0.0, i as f64 * 0.0081)).collect();
+        chain.push((10.0, 10.0));
+        let fitted = Dbscan::new(1.0, 2)
+            .metric(Haversine::new(Units::kilometers()))
+            .fit(&chain)
+            .unwrap();
+        assert_eq!(fitted.cluster_count, 1);
+        assert_eq!(fitted.labels[20], None);
+        assert!(fitted.core[..20].iter().all(|&core| core));
+
+        // the latitude band search finds the same neighbours as a full scan
+        let mut rng = fastrand::Rng::with_seed(9);
+        let scattered: Vec<(f64, f64)> = (0..800)
+            .map(|_| (rng.f64() * 4.0 + 40.0, rng.f64() * 4.0 - 76.0))
+            .collect();
+        let banded = Dbscan::new(15.0, 5)
+            .metric(Haversine::new(Units::kilometers()))
+            .fit(&scattered)
+            .unwrap();
+        let radius = Units::kilometers().radius();
+        let full_scan = Dbscan::new(15.0, 5)
+            .metric(move |a: &(f64, f64), b: &(f64, f64)| {
+                crate::user_data::LatLngType::haversine_with_radius(a, b, radius)
+            })
+            .fit(&scattered)
+            .unwrap();
+        assert!(banded.cluster_count > 1);
+        assert_eq!(banded.labels, full_scan.labels);
+
+        // projected meters get the planar mean of their members
+        let projected: [(f64, f64); 3] = [
+            (500_000.0, 4_500_000.0),
+            (500_010.0, 4_500_000.0),
+            (500_020.0, 4_500_030.0),
+        ];
+        let fitted = Dbscan::new(2_000.0, 2)
+            .metric(crate::distance::SquaredEuclidean)
+            .fit(&projected)
+            .unwrap();
+        assert_eq!(fitted.centroid, crate::nodes::CentroidMethod::Planar);
+        let location = fitted.clusters(&projected).unwrap().nodes[0].location;
+        assert!((location.0 - 500_010.0).abs() < 1e-6 && (location.1 - 4_500_010.0).abs() < 1e-6);
+
+        assert_eq!(
+            Dbscan::new(0.0, 2).fit(&chain).err(),
+            Some(crate::error::ClusterError::InvalidEps { eps: 0.0 })
+        );
+        assert_eq!(
+            Dbscan::new(1.0, 0).fit(&chain).err(),
+            Some(crate::error::ClusterError::ZeroMinPoints)
+        );
+    }
+
+    #[test]
+    fn test_hdbscan_variable_density() {
+        use crate::distance::{Haversine, Units};
+        use crate::hdbscan::Hdbscan;
+        let mut rng = fastrand::Rng::with_seed(12);
+        let mut pings = vec![];
+        // a city block a few hundred meters across and a county tens of km across
+        for _ in 0..60 {
+            pings.push((40.75 + rng.f64() * 0.004, -73.99 + rng.f64() * 0.004));
+        }
+        for _ in 0..60 {
+            pings.push((42.0 + rng.f64() * 0.4, -75.0 + rng.f64() * 0.4));
+        }
+        pings.push((45.0, -80.0));
+        let fitted = Hdbscan::new(10)
+            .metric(Haversine::new(Units::kilometers()))
+            .fit(&pings)
+            .unwrap();
+        assert_eq!(fitted.cluster_count, 2);
+        assert!(fitted.labels[..60].iter().all(|&l| l == fitted.labels[0]));
+        assert!(fitted.labels[60..120]
+            .iter()
+            .all(|&l| l == fitted.labels[60]));
+        assert_ne!(fitted.labels[0], fitted.labels[60]);
+        assert_eq!(fitted.labels[120], None);
+        assert_eq!(fitted.probabilities[120], 0.0);
+        assert!(fitted
+            .probabilities
+            .iter()
+            .chain(&fitted.outlier_scores)
+            .all(|&p| (0.0..=1.0).contains(&p)));
+        assert!(fitted.probabilities[..120].contains(&1.0));
+        assert!(fitted.outlier_scores[120] > 0.9);
+
+        let clusters = fitted.clusters(&pings).unwrap();
+        assert_eq!(clusters.nodes.len(), 2);
+        assert_eq!(clusters.noise, vec![&pings[120]]);
+
+        // exact duplicates give zero core distances without breaking the hierarchy
+        let fitted = Hdbscan::new(4).fit(&sample_data::DATASET_F64).unwrap();
+        assert!(fitted.cluster_count >= 2);
+        assert!(fitted.outlier_scores.iter().all(|score| score.is_finite()));
+
+        assert_eq!(
+            Hdbscan::new(1).fit(&pings).err(),
+            Some(crate::error::ClusterError::InvalidMinClusterSize {
+                min_cluster_size: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_optics_extractions() {
+        use crate::dbscan::Dbscan;
+        use crate::distance::{Haversine, Units};
+        use crate::optics::Optics;
+        let mut rng = fastrand::Rng::with_seed(30);
+        let mut pings = vec![];
+        for (lat, lng, spread) in [(40.0, -74.0, 0.05), (40.3, -74.0, 0.05), (41.5, -72.0, 0.3)] {
+            for _ in 0..50 {
+                pings.push((lat + rng.f64() * spread, lng + rng.f64() * spread));
+            }
+        }
+        for _ in 0..10 {
+            pings.push((35.0 + rng.f64() * 10.0, -90.0 + rng.f64() * 10.0));
+        }
+        let km = Haversine::new(Units::kilometers());
+        let fitted = Optics::new(5).metric(km).fit(&pings).unwrap();
+        let mut ordering = fitted.ordering.clone();
+        ordering.sort_unstable();
+        assert_eq!(ordering, (0..pings.len()).collect::<Vec<_>>());
+        assert_eq!(fitted.reachability_plot()[0], None);
+
+        // the same core clusters as running DBSCAN at every eps
+        for eps in [2.0, 5.0, 40.0] {
+            let extracted = fitted.extract_dbscan(eps).unwrap();
+            let dbscan = Dbscan::new(eps, 5).metric(km).fit(&pings).unwrap();
+            assert_eq!(extracted.cluster_count, dbscan.cluster_count);
+            assert_eq!(extracted.core, dbscan.core);
+            for a in 0..pings.len() {
+                // border points may be left out, never added
+                if extracted.labels[a].is_some() || extracted.core[a] {
+                    assert!(dbscan.labels[a].is_some());
+                    assert!(extracted.labels[a].is_some());
+                }
+                for b in 0..pings.len() {
+                    if extracted.core[a] && extracted.core[b] {
+                        assert_eq!(
+                            extracted.labels[a] == extracted.labels[b],
+                            dbscan.labels[a] == dbscan.labels[b]
+                        );
+                    }
+                }
+            }
+        }
+
+        // a larger min_points smooths the valleys inside each blob
+        let smooth = Optics::new(10).metric(km).fit(&pings).unwrap();
+        let xi = smooth.extract_xi(0.1).unwrap();
+        assert_eq!(xi.cluster_count, 3);
+        for blob in 0..3 {
+            let label = xi.labels[blob * 50 + 25];
+            assert!(label.is_some());
+            let matching = xi.labels[blob * 50..(blob + 1) * 50]
+                .iter()
+                .filter(|&&l| l == label)
+                .count();
+            assert!(matching >= 40, "blob {blob} has only {matching} points");
+        }
+        assert!(xi.ranges.len() >= 3);
+        let clusters = xi.clusters(&pings).unwrap();
+        assert_eq!(clusters.nodes.len(), 3);
+
+        let bounded = Optics::new(5).metric(km).max_eps(10.0).fit(&pings).unwrap();
+        assert_eq!(
+            bounded.extract_dbscan(20.0).err(),
+            Some(crate::error::ClusterError::InvalidEps { eps: 20.0 })
+        );
+        assert_eq!(
+            bounded.extract_xi(1.0).err(),
+            Some(crate::error::ClusterError::InvalidXi { xi: 1.0 })
+        );
+    }
+
+    #[test]
+    fn test_kmedoids_centers_are_data_points() {
+        use crate::distance::{DistanceMetric, Haversine, Units};
+        use crate::kmedoids::{KMedoids, MedoidAlgorithm};
+        let mut rng = fastrand::Rng::with_seed(18);
+        let mut sites = vec![];
+        for (lat, lng) in [(40.7, -74.0), (34.0, -118.2), (41.9, -87.6)] {
+            for _ in 0..40 {
+                sites.push((lat + rng.f64() * 0.2, lng + rng.f64() * 0.2));
+            }
+        }
+        // an outlier drags a mean but not a medoid
+        sites.push((45.0, -100.0));
+        let km = Haversine::new(Units::kilometers());
+
+        let pam = KMedoids::new(3)
+            .algorithm(MedoidAlgorithm::Pam)
+            .metric(km)
+            .fit(&sites)
+            .unwrap();
+        let faster = KMedoids::new(3).metric(km).fit(&sites).unwrap();
+        assert!(pam.model.report.converged && faster.model.report.converged);
+        assert!((pam.model.inertia - faster.model.inertia).abs() < 1e-6);
+        let mut blobs: Vec<usize> = faster.medoids.iter().map(|&m| m / 40).collect();
+        blobs.sort_unstable();
+        assert_eq!(blobs, vec![0, 1, 2]);
+
+        // no other choice of medoid lowers a node's total distance
+        let cluster = faster.cluster(&sites).unwrap();
+        for (node, medoid) in cluster
+            .nodes
+            .iter()
+            .zip(faster.medoid_points(&sites).unwrap())
+        {
+            assert_eq!(node.location, *medoid);
+            for candidate in node.children.iter() {
+                let total: f64 = node
+                    .children
+                    .iter()
+                    .map(|child| km.distance(candidate, child))
+                    .sum();
+                assert!(total >= node.total_distance - 1e-6);
+            }
+        }
+
+        let clara = KMedoids::new(3)
+            .algorithm(MedoidAlgorithm::clara())
+            .metric(km)
+            .seed(5)
+            .fit(&sites)
+            .unwrap();
+        assert!(clara.model.inertia <= faster.model.inertia * 1.05);
+        assert_eq!(
+            KMedoids::new(3)
+                .algorithm(MedoidAlgorithm::Clara {
+                    samples: 0,
+                    sample_size: None
+                })
+                .fit(&sites)
+                .err(),
+            Some(crate::error::ClusterError::ZeroRestarts)
+        );
+        assert!(faster.medoid_points(&sites[1..]).is_err());
+    }
+
+    #[test]
+    fn test_agglomerative_cuts() {
+        use crate::agglomerative::{Agglomerative, Linkage};
+        use crate::distance::{DistanceMetric, Haversine, Units};
+        let mut rng = fastrand::Rng::with_seed(19);
+        let mut pings = vec![];
+        // two metros per country, two countries
+        for (lat, lng) in [(40.7, -74.0), (42.3, -71.1), (51.5, -0.1), (53.5, -2.2)] {
+            for _ in 0..15 {
+                pings.push((lat + rng.f64() * 0.1, lng + rng.f64() * 0.1));
+            }
+        }
+        let km = Haversine::new(Units::kilometers());
+        let cross = |pick: fn(f64, f64) -> f64, init: f64| {
+            let mut result = init;
+            for a in &pings[..30] {
+                for b in &pings[30..] {
+                    result = pick(result, km.distance(a, b));
+                }
+            }
+            result
+        };
+
+        for linkage in [
+            Linkage::Single,
+            Linkage::Complete,
+            Linkage::Average,
+            Linkage::Ward,
+        ] {
+            let dendrogram = Agglomerative::new()
+                .linkage(linkage)
+                .metric(km)
+                .fit(&pings)
+                .unwrap();
+            assert_eq!(dendrogram.merges.len(), pings.len() - 1);
+            assert!(dendrogram
+                .merges
+                .windows(2)
+                .all(|pair| pair[0].distance <= pair[1].distance));
+            assert_eq!(dendrogram.merges.last().unwrap().size, pings.len());
+
+            let countries = dendrogram.labels_for_count(2).unwrap();
+            assert!(countries[..30].iter().all(|&label| label == 0));
+            assert!(countries[30..].iter().all(|&label| label == 1));
+            let metros = dendrogram.cut_count(4, &pings).unwrap();
+            for (metro, node) in metros.nodes.iter().enumerate() {
+                assert_eq!(node.children.len(), 15);
+                assert_eq!(metros.labels[metro * 15], metro);
+            }
+            for k in 1..=pings.len() {
+                let cut = dendrogram.labels_for_count(k).unwrap();
+                assert_eq!(cut.iter().max().unwrap() + 1, k);
+            }
+            let merged = dendrogram.merges[dendrogram.merges.len() - 3].distance;
+            assert_eq!(
+                dendrogram.cut_distance(merged, &pings).unwrap().nodes.len(),
+                3
+            );
+        }
+
+        let single = Agglomerative::new()
+            .linkage(Linkage::Single)
+            .metric(km)
+            .fit(&pings)
+            .unwrap();
+        let complete = Agglomerative::new()
+            .linkage(Linkage::Complete)
+            .metric(km)
+            .fit(&pings)
+            .unwrap();
+        assert!(
+            (single.merges.last().unwrap().distance - cross(f64::min, f64::INFINITY)).abs() < 1e-9
+        );
+        assert!((complete.merges.last().unwrap().distance - cross(f64::max, 0.0)).abs() < 1e-9);
+        assert_eq!(
+            single.labels_for_distance(-1.0).err(),
+            Some(crate::error::ClusterError::InvalidThreshold { threshold: -1.0 })
+        );
+        assert_eq!(single.labels_for_distance(0.0).unwrap().len(), pings.len());
+
+        // projected meters get the planar mean of their members
+        let projected: [(f64, f64); 3] = [
+            (500_000.0, 4_500_000.0),
+            (500_010.0, 4_500_000.0),
+            (500_020.0, 4_500_030.0),
+        ];
+        let dendrogram = Agglomerative::new()
+            .metric(crate::distance::SquaredEuclidean)
+            .fit(&projected)
+            .unwrap();
+        assert_eq!(dendrogram.centroid, crate::nodes::CentroidMethod::Planar);
+        let location = dendrogram.cut_count(1, &projected).unwrap().nodes[0].location;
+        assert!((location.0 - 500_010.0).abs() < 1e-6 && (location.1 - 4_500_010.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_k_selection_finds_blobs() {
+        use crate::distance::{Haversine, Units};
+        use crate::selection::{Criterion, KSelector};
+        use crate::KMeans;
+        let mut rng = fastrand::Rng::with_seed(20);
+        // gaussian blobs, the model behind the BIC
+        let mut normal = || {
+            let (u, v) = (1.0 - rng.f64(), rng.f64());
+            (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos() * 0.03
+        };
+        let mut pings = vec![];
+        for (lat, lng) in [(40.7, -74.0), (42.3, -71.1), (39.9, -75.2), (38.9, -77.0)] {
+            for _ in 0..25 {
+                pings.push((lat + normal(), lng + normal()));
+            }
+        }
+        let template = KMeans::new(1)
+            .metric(Haversine::new(Units::kilometers()))
+            .seed(4)
+            .n_init(3);
+        let selection = KSelector::new(2..=8)
+            .kmeans(template)
+            .criterion(Criterion::XMeans)
+            .fit(&pings)
+            .unwrap();
+        assert_eq!(selection.recommended, selection.xmeans);
+        assert_eq!(selection.elbow, 4);
+        assert_eq!(selection.silhouette, 4);
+        assert_eq!(selection.gap, 4);
+        assert_eq!(selection.xmeans, 4);
+        let ks: Vec<usize> = selection.scores.iter().map(|score| score.k).collect();
+        assert_eq!(ks, (2..=8).collect::<Vec<_>>());
+        assert!(selection
+            .scores
+            .windows(2)
+            .all(|pair| pair[1].inertia <= pair[0].inertia * 1.01));
+        assert!(selection.scores[2].bic > selection.scores[1].bic);
+
+        // the same seed gives the same table
+        let again = KSelector::new(2..=8).kmeans(template).fit(&pings).unwrap();
+        assert_eq!(again.scores, selection.scores);
+        assert_eq!(again.recommended, 4);
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = KSelector::new(5..=4);
+        assert_eq!(
+            empty.fit(&pings).err(),
+            Some(crate::error::ClusterError::EmptyKRange { min_k: 5, max_k: 4 })
+        );
+        assert_eq!(
+            KSelector::new(1..=200).fit(&pings).err(),
+            Some(crate::error::ClusterError::TooManyClusters { k: 200, n: 100 })
+        );
+    }
+
+    #[test]
+    fn test_quality_metrics() {
+        use crate::distance::{Haversine, Units};
+        use crate::metrics;
+        use crate::KMeans;
+        let km = Haversine::new(Units::kilometers());
+        let data = sample_data::DATASET_F64;
+        let fitted = KMeans::new(3).metric(km).seed(21).fit(&data).unwrap();
+        let cluster = fitted.cluster(&data).unwrap();
+
+        let from_cluster = metrics::evaluate_cluster(&cluster, &km).unwrap();
+        assert_eq!(from_cluster.inertia, fitted.model.inertia);
+        assert_eq!(from_cluster.silhouette_samples.len(), data.len());
+        assert!(from_cluster
+            .silhouette_samples
+            .iter()
+            .all(|s| (-1.0..=1.0).contains(s)));
+        // the node locations are the spherical centroids, so labels agree with the cluster
+        let from_labels = metrics::evaluate(&fitted.labels, &data, &km).unwrap();
+        assert!((from_labels.inertia - from_cluster.inertia).abs() < 1e-6);
+        assert_eq!(
+            from_labels.silhouette_samples,
+            from_cluster.silhouette_samples
+        );
+        assert!((from_labels.davies_bouldin - from_cluster.davies_bouldin).abs() < 1e-9);
+
+        // scrambling the labels makes every score worse
+        let scrambled: Vec<usize> = (0..data.len()).map(|i| i % 3).collect();
+        let worse = metrics::evaluate(&scrambled, &data, &km).unwrap();
+        assert!(worse.inertia > from_labels.inertia);
+        assert!(worse.silhouette < from_labels.silhouette);
+        assert!(worse.davies_bouldin > from_labels.davies_bouldin);
+        assert!(worse.calinski_harabasz < from_labels.calinski_harabasz);
+
+        assert_eq!(
+            metrics::silhouette_score(&vec![0; data.len()], &data, &km).err(),
+            Some(crate::error::ClusterError::InvalidClusterCount {
+                clusters: 1,
+                n: data.len()
+            })
+        );
+        assert!(metrics::inertia(&[0, 0], &data, &km).is_err());
+
+        // projected meters are measured against the planar centroids k-means used
+        let projected: Vec<(f64, f64)> = (0..10)
+            .map(|i| {
+                (
+                    500_000.0 + i as f64 * 7.0,
+                    4_500_000.0 + (i % 3) as f64 * 5.0,
+                )
+            })
+            .chain((0..10).map(|i| (510_000.0 + i as f64 * 3.0, 4_510_000.0 - i as f64)))
+            .collect();
+        let fitted = KMeans::new(2)
+            .metric(crate::distance::SquaredEuclidean)
+            .centroid(crate::nodes::CentroidMethod::Planar)
+            .seed(21)
+            .fit(&projected)
+            .unwrap();
+        let quality = metrics::evaluate(
+            &fitted.labels,
+            &projected,
+            &crate::distance::SquaredEuclidean,
+        )
+        .unwrap();
+        assert!((quality.inertia - fitted.model.inertia).abs() < 1e-6 * fitted.model.inertia);
+    }
+
+    #[test]
+    fn test_comparison_scores() {
+        use crate::comparison;
+        use crate::distance::{Haversine, Units};
+        use crate::KMeans;
+        let km = Haversine::new(Units::kilometers());
+        let data = sample_data::DATASET_F64;
+
+        // textbook values, as computed by scikit-learn
+        let truth = [0, 0, 0, 1, 1, 1];
+        let predicted = [0, 0, 1, 1, 2, 2];
+        let ari = comparison::adjusted_rand_index(&truth, &predicted).unwrap();
+        assert!((ari - 0.242_424_242_424_242_4).abs() < 1e-12);
+        let nmi = comparison::normalized_mutual_information(&truth, &predicted).unwrap();
+        assert!((nmi - 0.515_803_742_979_388_8).abs() < 1e-12);
+        let v = comparison::homogeneity_completeness_v_measure(&truth, &predicted).unwrap();
+        assert!((v.homogeneity - 0.666_666_666_666_666_6).abs() < 1e-12);
+        assert!((v.completeness - 0.420_619_835_714_305).abs() < 1e-12);
+        assert!((v.v_measure - 0.515_803_742_979_388_8).abs() < 1e-12);
+        assert_eq!(
+            comparison::adjusted_rand_index(&[0, 0], &[5, 5]).unwrap(),
+            1.0
+        );
+
+        // the same clustering with another seed is the same up to renumbering
+        let first = KMeans::new(3)
+            .metric(km)
+            .seed(1)
+            .n_init(5)
+            .fit(&data)
+            .unwrap();
+        let second = KMeans::new(3)
+            .metric(km)
+            .seed(2)
+            .n_init(5)
+            .fit(&data)
+            .unwrap();
+        let compared = comparison::compare(
+            &first.cluster(&data).unwrap(),
+            &second.cluster(&data).unwrap(),
+            &km,
+        )
+        .unwrap();
+        assert_eq!(compared.adjusted_rand_index, 1.0);
+        assert!((compared.v_measure - 1.0).abs() < 1e-12);
+        assert_eq!(compared.centroids.pairs.len(), 3);
+        assert!(compared.centroids.max_distance < 1e-6);
+        for pair in compared.centroids.pairs.iter() {
+            assert_eq!(first.centroids()[pair.from], second.centroids()[pair.to]);
+        }
+
+        // more centroids on one side leaves the farthest one unmatched
+        let fewer = [(40.0, -74.0), (51.5, -0.1)];
+        let more = [(51.6, -0.1), (35.7, 139.7), (40.1, -74.0)];
+        let matching = comparison::match_centroids(&more, &fewer, &km);
+        assert_eq!(matching.unmatched_from, vec![1]);
+        assert_eq!(
+            matching
+                .pairs
+                .iter()
+                .map(|p| (p.from, p.to))
+                .collect::<Vec<_>>(),
+            vec![(0, 1), (2, 0)]
+        );
+        assert!(comparison::adjusted_rand_index(&[0, 1], &[0]).is_err());
+
+        assert_eq!(comparison::adjusted_rand_index(&[0], &[0]).unwrap(), 1.0);
+
+        // one label per point stays linear in memory
+        let singletons: Vec<usize> = (0..60_000).collect();
+        let reversed: Vec<usize> = singletons.iter().rev().copied().collect();
+        assert_eq!(
+            comparison::adjusted_rand_index(&singletons, &reversed).unwrap(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_weighted_data_points() {
+        use crate::agglomerative::{Agglomerative, Linkage};
+        use crate::distance::Haversine;
+        use crate::metrics;
+        use crate::nodes::Node;
+        use crate::user_data::UserDataType;
+
+        #[derive(Clone, Debug)]
+        struct Site {
+            lat: f64,
+            lng: f64,
+            volume: f64,
+        }
+        impl UserDataType<f64> for Site {
+            fn get_coords(&self) -> (f64, f64) {
+                (self.lat, self.lng)
+            }
+            fn weight(&self) -> f64 {
+                self.volume
+            }
+        }
+
+        // a warehouse pulls the centroid nine times harder than a kiosk
+        let pair = vec![
+            Site {
+                lat: 40.0,
+                lng: -74.0,
+                volume: 9.0,
+            },
+            Site {
+                lat: 41.0,
+                lng: -74.0,
+                volume: 1.0,
+            },
+        ];
+        let mut node = Node::new(0.0, 0.0, pair.clone());
+        let (lat, _) = node.calculate_planar_centroid().unwrap();
+        assert!((lat - 40.1).abs() < 1e-12);
+        let (lat, lng) = node.calculate_new_centroid().unwrap();
+        assert!((lat - 40.1).abs() < 1e-3 && (lng + 74.0).abs() < 1e-9);
+
+        // integer weights cluster like the sites repeated that many times
+        let mut rng = fastrand::Rng::with_seed(24);
+        let mut sites = vec![];
+        for (lat, lng) in [(40.7, -74.0), (34.0, -118.2)] {
+            for _ in 0..20 {
+                sites.push(Site {
+                    lat: lat + rng.f64() * 0.5,
+                    lng: lng + rng.f64() * 0.5,
+                    volume: rng.usize(1..5) as f64,
+                });
+            }
+        }
+        let repeated: Vec<(f64, f64)> = sites
+            .iter()
+            .flat_map(|site| std::iter::repeat_n(site.get_coords(), site.volume as usize))
+            .collect();
+        let weighted = super::KMeans::new(2).seed(3).fit(&sites).unwrap();
+        let copies = super::KMeans::new(2).seed(3).fit(&repeated).unwrap();
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9 * a.abs().max(1.0);
+        assert!(close(weighted.model.inertia, copies.model.inertia));
+        let sorted = |mut centroids: Vec<(f64, f64)>| {
+            centroids.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            centroids
+        };
+        for (a, b) in sorted(weighted.model.centroids.clone())
+            .iter()
+            .zip(sorted(copies.model.centroids.clone()).iter())
+        {
+            assert!(close(a.0, b.0) && close(a.1, b.1));
+        }
+        let cluster = weighted.cluster(&sites).unwrap();
+        let total: f64 = cluster.nodes.iter().map(|node| node.total_distance).sum();
+        assert!(close(total, weighted.model.inertia));
+
+        let haversine = Haversine::default();
+        let by_weight = metrics::evaluate(&weighted.labels, &sites, &haversine).unwrap();
+        let by_copies = metrics::evaluate(&copies.labels, &repeated, &haversine).unwrap();
+        assert!(close(by_weight.inertia, by_copies.inertia));
+        assert!(close(by_weight.davies_bouldin, by_copies.davies_bouldin));
+        assert!(close(
+            by_weight.calinski_harabasz,
+            by_copies.calinski_harabasz
+        ));
+        assert!(by_weight.silhouette > 0.9);
+
+        // density and linkage count a site by its weight as well
+        let heavy = [
+            Site {
+                lat: 40.0,
+                lng: -74.0,
+                volume: 3.0,
+            },
+            Site {
+                lat: 41.0,
+                lng: -74.0,
+                volume: 1.0,
+            },
+        ];
+        let dbscan = crate::dbscan::Dbscan::new(1.0, 3).fit(&heavy).unwrap();
+        assert_eq!(dbscan.labels, vec![Some(0), None]);
+        let optics = crate::optics::Optics::new(3).fit(&heavy).unwrap();
+        assert_eq!(optics.core_distances[0], Some(0.0));
+        for linkage in [Linkage::Average, Linkage::Ward] {
+            let by_weight = Agglomerative::new().linkage(linkage).fit(&sites).unwrap();
+            let by_copies = Agglomerative::new()
+                .linkage(linkage)
+                .fit(&repeated)
+                .unwrap();
+            assert!(close(
+                by_weight.merges.last().unwrap().distance,
+                by_copies.merges.last().unwrap().distance
+            ));
+        }
+
+        let mut invalid = pair;
+        invalid[1].volume = 0.0;
+        assert_eq!(
+            super::KMeans::new(1).fit(&invalid).err(),
+            Some(crate::error::ClusterError::InvalidWeight {
+                index: 1,
+                weight: 0.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_balanced_respects_capacities() {
+        use crate::balanced::{BalancedKMeans, Capacity, CapacityMeasure};
+        use crate::distance::{DistanceMetric, Haversine};
+        use crate::error::ClusterError;
+        use crate::user_data::UserDataType;
+
+        let data = &sample_data::DATASET_F64;
+        let sizes = |labels: &[usize], k: usize| {
+            let mut sizes = vec![0; k];
+            for &label in labels {
+                sizes[label] += 1;
+            }
+            sizes
+        };
+        let fitted = BalancedKMeans::new(4)
+            .capacity(Capacity::at_most(13.0))
+            .seed(25)
+            .fit(data)
+            .unwrap();
+        assert_eq!(sizes(&fitted.labels, 4), vec![13; 4]);
+        assert_eq!(fitted.loads, vec![13.0; 4]);
+        // no two points would rather swap nodes
+        let haversine = Haversine::default();
+        let centroids = &fitted.model.centroids;
+        let distance = |point: usize, node: usize| {
+            haversine.distance(&centroids[node], &data[point].get_coords())
+        };
+        for i in 0..data.len() {
+            for j in 0..data.len() {
+                let (a, b) = (fitted.labels[i], fitted.labels[j]);
+                assert!(distance(i, b) + distance(j, a) >= distance(i, a) + distance(j, b) - 1e-6);
+            }
+        }
+        let cluster = fitted.cluster(data).unwrap();
+        assert!(cluster.nodes.iter().all(|node| node.children.len() == 13));
+
+        let even = BalancedKMeans::new(4).seed(25).fit(data).unwrap();
+        assert_eq!(sizes(&even.labels, 4), vec![13; 4]);
+        for k in [3, 5, 7] {
+            let uneven = BalancedKMeans::new(k).seed(25).fit(data).unwrap();
+            let sizes = sizes(&uneven.labels, k);
+            assert_eq!(sizes.iter().sum::<usize>(), 52);
+            assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+        }
+        let at_least = BalancedKMeans::new(4)
+            .capacity(Capacity::between(10.0, 52.0))
+            .seed(25)
+            .fit(data)
+            .unwrap();
+        assert!(sizes(&at_least.labels, 4).iter().all(|&size| size >= 10));
+
+        assert_eq!(
+            BalancedKMeans::new(4)
+                .capacity(Capacity::at_most(10.0))
+                .fit(data)
+                .err(),
+            Some(ClusterError::InfeasibleCapacity {
+                total: 52.0,
+                min_total: 0.0,
+                max_total: 40.0
+            })
+        );
+        assert_eq!(
+            BalancedKMeans::new(4)
+                .capacity(Capacity::between(5.0, 3.0))
+                .fit(data)
+                .err(),
+            Some(ClusterError::InvalidCapacity {
+                node: 0,
+                min: 5.0,
+                max: 3.0
+            })
+        );
+        assert_eq!(
+            BalancedKMeans::new(4)
+                .capacities(vec![Capacity::unbounded(); 3])
+                .fit(data)
+                .err(),
+            Some(ClusterError::CapacityCount { k: 4, found: 3 })
+        );
+
+        #[derive(Clone, Debug)]
+        struct Depot {
+            coords: (f64, f64),
+            demand: f64,
+        }
+        impl UserDataType<f64> for Depot {
+            fn get_coords(&self) -> (f64, f64) {
+                self.coords
+            }
+            fn weight(&self) -> f64 {
+                self.demand
+            }
+        }
+        let mut rng = fastrand::Rng::with_seed(25);
+        let depots: Vec<Depot> = data
+            .iter()
+            .map(|point| Depot {
+                coords: point.get_coords(),
+                demand: rng.usize(1..4) as f64,
+            })
+            .collect();
+        let total: f64 = depots.iter().map(|depot| depot.demand).sum();
+        let fitted = BalancedKMeans::new(3)
+            .measure(CapacityMeasure::Weight)
+            .capacity(Capacity::at_most(total / 3.0 + 3.0))
+            .seed(25)
+            .fit(&depots)
+            .unwrap();
+        assert!((fitted.loads.iter().sum::<f64>() - total).abs() < 1e-9);
+        assert!(fitted.loads.iter().all(|&load| load <= total / 3.0 + 3.0));
+    }
+
+    #[test]
+    fn test_balanced_routes_light_points() {
+        use crate::balanced::{BalancedKMeans, Capacity, CapacityMeasure};
+        use crate::user_data::UserDataType;
+
+        #[derive(Clone, Debug)]
+        struct Stop {
+            coords: (f64, f64),
+            demand: f64,
+        }
+        impl UserDataType<f64> for Stop {
+            fn get_coords(&self) -> (f64, f64) {
+                self.coords
+            }
+            fn weight(&self) -> f64 {
+                self.demand
+            }
+        }
+        // the last stop weighs far less than a trillionth of the total
+        let stops: Vec<Stop> = [
+            (40.71, -74.00),
+            (40.72, -74.01),
+            (51.50, -0.12),
+            (51.51, -0.13),
+        ]
+        .iter()
+        .zip([1e6, 1e6, 1e6, 1e-7])
+        .map(|(&coords, demand)| Stop { coords, demand })
+        .collect();
+        let fitted = BalancedKMeans::new(2)
+            .measure(CapacityMeasure::Weight)
+            .capacity(Capacity::unbounded())
+            .seed(1)
+            .fit(&stops)
+            .unwrap();
+        assert_eq!(fitted.labels.len(), 4);
+        assert_eq!(fitted.labels[2], fitted.labels[3]);
+        assert!((fitted.loads.iter().sum::<f64>() - 3e6 - 1e-7).abs() < 1e-6);
+    }
 }
@@ -0,0 +1,392 @@
+//! quality of a clustering, computed with the same distance metric it was fitted with
+//!
+//! every score can be taken from a [Cluster] or from a label per data point. a cluster is
+//! measured against its nodes' locations, labels against the centroid of the data points
+//! sharing a label. centroids are spherical for lat/lng metrics and planar for the rest
+//!
+//! every score weighs the data points by their [UserDataType::weight]
+//!
+//! Example:
+//! ```
+//! use coordinate_cluster::distance::{Haversine, Units};
+//! use coordinate_cluster::metrics;
+//! use coordinate_cluster::KMeans;
+//!
+//! let pings: [(f64, f64); 6] = [
+//!     (40.7128, -74.0060),
+//!     (40.7130, -74.0050),
+//!     (40.7120, -74.0070),
+//!     (51.5074, -0.1278),
+//!     (51.5080, -0.1270),
+//!     (51.5070, -0.1280),
+//! ];
+//! let km = Haversine::new(Units::kilometers());
+//! let fitted = KMeans::new(2).metric(km).seed(1).fit(&pings).unwrap();
+//! let cluster = fitted.cluster(&pings).unwrap();
+//!
+//! let quality = metrics::evaluate_cluster(&cluster, &km).unwrap();
+//! assert!(quality.silhouette > 0.99);
+//! assert!(quality.davies_bouldin < 0.01);
+//! assert_eq!(quality.inertia, fitted.model.inertia);
+//!
+//! // a bad split scores worse
+//! let mixed = [0, 1, 0, 1, 0, 1];
+//! assert!(metrics::silhouette_score(&mixed, &pings, &km).unwrap() < 0.0);
+//! ```
+use crate::cluster::Cluster;
+use crate::distance::{DistanceMetric, DistanceUnit};
+use crate::error::ClusterError;
+use crate::nodes::{CentroidAccumulator, CentroidMethod};
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+
+/// every score of [evaluate]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quality<T: LatLngType> {
+    /// total weighted distance of the data points to their centroid, lower is better
+    pub inertia: T,
+    /// weighted mean of `silhouette_samples` in [-1, 1], higher is better
+    pub silhouette: f64,
+    /// how much closer each data point is to its own cluster than to the next closest
+    /// one, in [-1, 1]. in the order of the labels
+    pub silhouette_samples: Vec<f64>,
+    /// mean over the clusters of the worst ratio of spread to separation, lower is
+    /// better and 0 at best
+    pub davies_bouldin: f64,
+    /// squared separation of the centroids over squared spread within the clusters,
+    /// each per degree of freedom. higher is better
+    pub calinski_harabasz: f64,
+    /// unit of `inertia`, None when the metric is not a physical length
+    pub unit: Option<DistanceUnit>,
+}
+
+/// every score of the clustering given by `labels`, one per data point in input order
+///
+/// the silhouette measures every pair of data points, n² distance calls
+pub fn evaluate<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<Quality<T>, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    Labelled::from_labels(labels, data_points, metric)?.quality(metric)
+}
+
+/// every score of a clustering result, measured against the nodes' locations
+///
+/// the per point silhouettes follow `cluster.labels` when it describes the children,
+/// as for every result of this crate, and the order of the nodes' children otherwise
+pub fn evaluate_cluster<T, DATAPOINT, M>(
+    cluster: &Cluster<T, DATAPOINT>,
+    metric: &M,
+) -> Result<Quality<T>, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T> + Clone,
+    M: DistanceMetric<T>,
+{
+    Labelled::from_cluster(cluster)?.quality(metric)
+}
+
+/// total weighted distance of the data points to the centroid of their label
+pub fn inertia<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<T, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    Ok(Labelled::from_labels(labels, data_points, metric)?.inertia(metric))
+}
+
+/// silhouette of every data point in input order, see [Quality::silhouette_samples]
+pub fn silhouette_samples<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<Vec<f64>, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    let labelled = Labelled::from_labels(labels, data_points, metric)?;
+    labelled.check_cluster_count()?;
+    Ok(labelled.silhouette_samples(metric))
+}
+
+/// mean silhouette over every data point, see [Quality::silhouette]
+pub fn silhouette_score<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<f64, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    let labelled = Labelled::from_labels(labels, data_points, metric)?;
+    labelled.check_cluster_count()?;
+    let samples = labelled.silhouette_samples(metric);
+    Ok(parallel::weighted_mean(&samples, &labelled.weights))
+}
+
+/// see [Quality::davies_bouldin]
+pub fn davies_bouldin<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<f64, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    let labelled = Labelled::from_labels(labels, data_points, metric)?;
+    labelled.check_cluster_count()?;
+    Ok(labelled.davies_bouldin(metric))
+}
+
+/// see [Quality::calinski_harabasz]
+pub fn calinski_harabasz<T, DATAPOINT, M>(
+    labels: &[usize],
+    data_points: &[DATAPOINT],
+    metric: &M,
+) -> Result<f64, ClusterError>
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    let labelled = Labelled::from_labels(labels, data_points, metric)?;
+    labelled.check_cluster_count()?;
+    Ok(labelled.calinski_harabasz(metric))
+}
+
+/// coordinates with a label each and the centroid every label is measured against,
+/// labels without any data point are left out of every score
+struct Labelled<T: LatLngType> {
+    coords: Vec<(T, T)>,
+    weights: Vec<f64>,
+    labels: Vec<usize>,
+    centroids: Vec<(T, T)>,
+    sizes: Vec<usize>,
+    /// summed weight of each label
+    masses: Vec<f64>,
+}
+
+impl<T: LatLngType> Labelled<T> {
+    fn from_labels<DATAPOINT, M>(
+        labels: &[usize],
+        data_points: &[DATAPOINT],
+        metric: &M,
+    ) -> Result<Self, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: DistanceMetric<T>,
+    {
+        if data_points.len() != labels.len() {
+            return Err(ClusterError::LengthMismatch {
+                expected: labels.len(),
+                found: data_points.len(),
+            });
+        }
+        validate_data_points(1, data_points, metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let k = labels.iter().max().map_or(0, |&max| max + 1);
+        let method = CentroidMethod::for_metric(metric);
+        let mut accumulators = vec![CentroidAccumulator::default(); k];
+        for ((&coord, &weight), &label) in coords.iter().zip(&weights).zip(labels) {
+            accumulators[label].push(coord, weight);
+        }
+        Ok(Self {
+            coords,
+            weights,
+            labels: labels.to_vec(),
+            centroids: accumulators
+                .iter()
+                .map(|acc| acc.centroid(method).unwrap_or_default())
+                .collect(),
+            sizes: accumulators.iter().map(|acc| acc.count()).collect(),
+            masses: accumulators.iter().map(|acc| acc.weight()).collect(),
+        })
+    }
+
+    fn from_cluster<DATAPOINT>(cluster: &Cluster<T, DATAPOINT>) -> Result<Self, ClusterError>
+    where
+        DATAPOINT: UserDataType<T> + Clone,
+    {
+        let sizes: Vec<usize> = cluster
+            .nodes
+            .iter()
+            .map(|node| node.children.len())
+            .collect();
+        let n: usize = sizes.iter().sum();
+        if n == 0 {
+            return Err(ClusterError::EmptyInput);
+        }
+        let mut label_counts = vec![0; sizes.len()];
+        for &label in cluster.labels.iter() {
+            match label_counts.get_mut(label) {
+                Some(count) => *count += 1,
+                None => break,
+            }
+        }
+        let labels_describe_children = cluster.labels.len() == n && label_counts == sizes;
+        let labels: Vec<usize> = match labels_describe_children {
+            true => cluster.labels.clone(),
+            false => (0..sizes.len())
+                .flat_map(|node| std::iter::repeat_n(node, sizes[node]))
+                .collect(),
+        };
+        // the children of each node are taken in turn, in the order of the labels
+        let mut next_child = vec![0; sizes.len()];
+        let children: Vec<&DATAPOINT> = labels
+            .iter()
+            .map(|&label| {
+                next_child[label] += 1;
+                &cluster.nodes[label].children[next_child[label] - 1]
+            })
+            .collect();
+        let weights: Vec<f64> = children.iter().map(|child| child.weight()).collect();
+        let mut masses = vec![0.0; sizes.len()];
+        for (&label, &weight) in labels.iter().zip(&weights) {
+            masses[label] += weight;
+        }
+        Ok(Self {
+            coords: children.iter().map(|child| child.get_coords()).collect(),
+            weights,
+            labels,
+            centroids: cluster.nodes.iter().map(|node| node.location).collect(),
+            sizes,
+            masses,
+        })
+    }
+
+    fn cluster_count(&self) -> usize {
+        self.sizes.iter().filter(|&&size| size > 0).count()
+    }
+
+    /// the scores besides inertia compare clusters, they need at least two and fewer
+    /// clusters than data points
+    fn check_cluster_count(&self) -> Result<(), ClusterError> {
+        let (clusters, n) = (self.cluster_count(), self.coords.len());
+        if clusters < 2 || clusters >= n {
+            return Err(ClusterError::InvalidClusterCount { clusters, n });
+        }
+        Ok(())
+    }
+
+    fn quality<M: DistanceMetric<T>>(&self, metric: &M) -> Result<Quality<T>, ClusterError> {
+        self.check_cluster_count()?;
+        let silhouette_samples = self.silhouette_samples(metric);
+        Ok(Quality {
+            inertia: self.inertia(metric),
+            silhouette: parallel::weighted_mean(&silhouette_samples, &self.weights),
+            silhouette_samples,
+            davies_bouldin: self.davies_bouldin(metric),
+            calinski_harabasz: self.calinski_harabasz(metric),
+            unit: metric.unit(),
+        })
+    }
+
+    /// distance of every data point to its centroid
+    fn distances<M: DistanceMetric<T>>(&self, metric: &M) -> Vec<T> {
+        let mut distances = vec![T::default(); self.coords.len()];
+        parallel::measure(
+            &self.coords,
+            &self.centroids,
+            metric,
+            &self.labels,
+            &mut distances,
+        );
+        distances
+    }
+
+    fn inertia<M: DistanceMetric<T>>(&self, metric: &M) -> T {
+        let (_, totals) = parallel::accumulate(
+            self.centroids.len(),
+            &self.coords,
+            &self.weights,
+            &self.labels,
+            &self.distances(metric),
+        );
+        totals.iter().fold(T::default(), |acc, &total| acc + total)
+    }
+
+    fn silhouette_samples<M: DistanceMetric<T>>(&self, metric: &M) -> Vec<f64> {
+        let coords = &self.coords;
+        parallel::silhouette(&self.labels, &self.weights, self.centroids.len(), |a, b| {
+            metric.distance(&coords[a], &coords[b]).to_f64()
+        })
+    }
+
+    fn davies_bouldin<M: DistanceMetric<T>>(&self, metric: &M) -> f64 {
+        let mut spread = vec![0.0; self.centroids.len()];
+        let distances = self.distances(metric);
+        for ((&label, distance), weight) in self.labels.iter().zip(distances).zip(&self.weights) {
+            spread[label] += distance.to_f64() * weight;
+        }
+        let clusters: Vec<usize> = (0..self.sizes.len())
+            .filter(|&cluster| self.sizes[cluster] > 0)
+            .collect();
+        clusters
+            .iter()
+            .for_each(|&cluster| spread[cluster] /= self.masses[cluster]);
+        let worst = |&i: &usize| {
+            clusters
+                .iter()
+                .filter(|&&j| j != i)
+                .map(|&j| {
+                    let separation = metric
+                        .distance(&self.centroids[i], &self.centroids[j])
+                        .to_f64();
+                    match separation > 0.0 {
+                        true => (spread[i] + spread[j]) / separation,
+                        false => f64::INFINITY,
+                    }
+                })
+                .fold(0.0, f64::max)
+        };
+        clusters.iter().map(worst).sum::<f64>() / clusters.len() as f64
+    }
+
+    fn calinski_harabasz<M: DistanceMetric<T>>(&self, metric: &M) -> f64 {
+        let mut overall = CentroidAccumulator::default();
+        self.coords
+            .iter()
+            .zip(&self.weights)
+            .for_each(|(&coord, &weight)| overall.push(coord, weight));
+        let center = overall
+            .centroid(CentroidMethod::for_metric(metric))
+            .unwrap_or_default();
+        let between: f64 = self
+            .centroids
+            .iter()
+            .zip(&self.masses)
+            .map(|(centroid, &mass)| mass * metric.distance(centroid, &center).to_f64().powi(2))
+            .sum();
+        let within: f64 = self
+            .distances(metric)
+            .iter()
+            .zip(&self.weights)
+            .map(|(distance, weight)| distance.to_f64().powi(2) * weight)
+            .sum();
+        if within == 0.0 {
+            return 1.0;
+        }
+        let n: f64 = self.weights.iter().sum();
+        let clusters = self.cluster_count() as f64;
+        (between / (clusters - 1.0)) / (within / (n - clusters))
+    }
+}
@@ -0,0 +1,229 @@
+use crate::distance::{DistanceMetric, Haversine};
+use crate::error::ClusterError;
+use crate::init::InitStrategy;
+use crate::kmeans::{self, FittedKMeans};
+use crate::nodes::CentroidMethod;
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::{validate_data_points, ConvergenceReport};
+
+/// builder for mini-batch k-means, for inputs too large to visit every data point
+/// every round
+///
+/// each round draws `batch_size` random data points, assigns them to the current
+/// centroids and moves every centroid towards its batch points one at a time. the
+/// learning rate of a centroid is 1 / (number of points it has absorbed so far), so
+/// busy centroids settle while rarely hit ones keep moving. a single full pass at the
+/// end labels every data point
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::minibatch::MiniBatchKMeans;
+///
+/// let pings: Vec<(f64, f64)> = (0..2000)
+///     .map(|i| match i % 2 {
+///         0 => (40.71 + (i % 7) as f64 * 0.001, -74.00),
+///         _ => (51.50, -0.12 + (i % 5) as f64 * 0.001),
+///     })
+///     .collect();
+/// let fitted = MiniBatchKMeans::new(2)
+///     .batch_size(64)
+///     .max_iterations(50)
+///     .seed(3)
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(fitted.model.sizes, vec![1000, 1000]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct MiniBatchKMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    k: usize,
+    batch_size: usize,
+    max_iterations: usize,
+    tolerance: T,
+    init: InitStrategy,
+    init_size: Option<usize>,
+    centroid: CentroidMethod,
+    metric: M,
+    seed: Option<u64>,
+}
+
+impl<T: LatLngType> MiniBatchKMeans<T, Haversine> {
+    /// k clusters, batches of 1024 points and at most 100 batches
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            batch_size: 1024,
+            max_iterations: 100,
+            tolerance: T::default(),
+            init: InitStrategy::default(),
+            init_size: None,
+            centroid: CentroidMethod::default(),
+            metric: Haversine::default(),
+            seed: None,
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> MiniBatchKMeans<T, M> {
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// data points drawn per round, with replacement
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// upper bound on the number of batches
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// stop once no centroid moves further than this over one batch, in the units of
+    /// the metric. the default of 0 runs every batch and never reports the run as
+    /// converged
+    pub fn tolerance(mut self, tolerance: T) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn init(mut self, init: InitStrategy) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// number of random data points the initial centroids are picked from,
+    /// defaults to three batches. seeding on the whole input would cost a full pass
+    /// per centroid
+    pub fn init_size(mut self, init_size: usize) -> Self {
+        self.init_size = Some(init_size);
+        self
+    }
+
+    /// how a centroid moves towards a point, see [CentroidMethod]
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = centroid;
+        self
+    }
+
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> MiniBatchKMeans<T, M2> {
+        MiniBatchKMeans {
+            k: self.k,
+            batch_size: self.batch_size,
+            max_iterations: self.max_iterations,
+            tolerance: self.tolerance,
+            init: self.init,
+            init_size: self.init_size,
+            centroid: self.centroid,
+            metric,
+            seed: self.seed,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedKMeans<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        if self.max_iterations == 0 {
+            return Err(ClusterError::ZeroIterations);
+        }
+        if self.batch_size == 0 {
+            return Err(ClusterError::ZeroBatchSize);
+        }
+        validate_data_points(self.k, data_points, &self.metric)?;
+
+        // the batches read straight from the data points, only the final pass copies them
+        let n = data_points.len();
+        let mut rng = self.seed.map(fastrand::Rng::with_seed).unwrap_or_default();
+        let init_size = self
+            .init_size
+            .unwrap_or(3 * self.batch_size)
+            .clamp(self.k, n);
+        let sample: Vec<usize> = if init_size == n {
+            (0..n).collect()
+        } else {
+            (0..init_size).map(|_| rng.usize(..n)).collect()
+        };
+        let sample_coords: Vec<(T, T)> = sample
+            .iter()
+            .map(|&index| data_points[index].get_coords())
+            .collect();
+        let sample_weights: Vec<f64> = sample
+            .iter()
+            .map(|&index| data_points[index].weight())
+            .collect();
+        let mut centroids = self.init.weighted_centroids(
+            self.k,
+            &sample_coords,
+            &sample_weights,
+            &self.metric,
+            &mut rng,
+        );
+
+        // weight each centroid has absorbed so far, a point moves it by its share of that
+        let mut absorbed = vec![0.0; self.k];
+        let mut batch = vec![(T::default(), T::default()); self.batch_size];
+        let mut batch_weights = vec![0.0; self.batch_size];
+        let mut labels = vec![0; self.batch_size];
+        let mut distances = vec![T::default(); self.batch_size];
+        let mut report = ConvergenceReport {
+            iterations: 0,
+            converged: false,
+            max_centroid_shift: T::default(),
+        };
+        while report.iterations < self.max_iterations {
+            report.iterations += 1;
+            for (coord, weight) in batch.iter_mut().zip(batch_weights.iter_mut()) {
+                let data_point = &data_points[rng.usize(..n)];
+                (*coord, *weight) = (data_point.get_coords(), data_point.weight());
+            }
+            parallel::assign(
+                &batch,
+                &centroids,
+                &self.metric,
+                &mut labels,
+                &mut distances,
+            );
+
+            let previous = centroids.clone();
+            for ((&coord, &weight), &label) in batch.iter().zip(&batch_weights).zip(&labels) {
+                absorbed[label] += weight;
+                let rate = weight / absorbed[label];
+                centroids[label] = self.centroid.step(centroids[label], coord, rate);
+            }
+
+            report.max_centroid_shift = previous
+                .iter()
+                .zip(&centroids)
+                .map(|(before, after)| self.metric.distance(before, after))
+                .fold(
+                    T::default(),
+                    |max, shift| if shift > max { shift } else { max },
+                );
+            if report.max_centroid_shift <= self.tolerance && self.tolerance > T::default() {
+                report.converged = true;
+                break;
+            }
+        }
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        Ok(kmeans::fitted_from_centroids(
+            &coords,
+            &weights,
+            centroids,
+            &self.metric,
+            report,
+        ))
+    }
+}
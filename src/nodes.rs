@@ -1,7 +1,180 @@
+use crate::distance::DistanceMetric;
 use crate::user_data::{LatLngType, UserDataType};
 
+/// how a node derives its centroid from its children
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CentroidMethod {
+    /// mean of the children as 3d unit vectors projected back to lat/lng,
+    /// correct across the antimeridian and near the poles
+    ///
+    /// when the vectors cancel out (e.g. two antipodal points) there is no meaningful
+    /// direction and the planar mean is used instead
+    #[default]
+    Spherical,
+    /// arithmetic mean of latitudes and longitudes, only sensible for small
+    /// local datasets that do not cross ±180°
+    Planar,
+}
+
+/// below this length the mean unit vector is treated as having no direction
+const DEGENERATE_VECTOR_LENGTH: f64 = 1e-9;
+
+impl CentroidMethod {
+    /// [CentroidMethod::Spherical] for metrics over lat/lng, [CentroidMethod::Planar] for
+    /// projected coordinates
+    pub(crate) fn for_metric<T: LatLngType, M: DistanceMetric<T>>(metric: &M) -> Self {
+        match metric.expects_lat_lng() {
+            true => CentroidMethod::Spherical,
+            false => CentroidMethod::Planar,
+        }
+    }
+
+    /// move `centroid` the fraction `rate` of the way towards `point`. with a rate of 1/n
+    /// for the nth point the planar step reproduces the running mean one point at a time,
+    /// the spherical one only approximates the spherical mean since every step restarts
+    /// from a unit vector
+    pub(crate) fn step<T: LatLngType>(&self, centroid: (T, T), point: (T, T), rate: f64) -> (T, T) {
+        if centroid == point {
+            return centroid;
+        }
+        let planar = || {
+            let lat = centroid.0.to_f64() + (point.0.to_f64() - centroid.0.to_f64()) * rate;
+            let lng = centroid.1.to_f64() + (point.1.to_f64() - centroid.1.to_f64()) * rate;
+            (T::from_f64(lat), T::from_f64(lng))
+        };
+        if *self == CentroidMethod::Planar {
+            return planar();
+        }
+        let (x1, y1, z1) = unit_vector(centroid);
+        let (x2, y2, z2) = unit_vector(point);
+        let x = x1 + (x2 - x1) * rate;
+        let y = y1 + (y2 - y1) * rate;
+        let z = z1 + (z2 - z1) * rate;
+        if (x * x + y * y + z * z).sqrt() < DEGENERATE_VECTOR_LENGTH {
+            return planar();
+        }
+        let lat = z.atan2(x.hypot(y)).to_degrees();
+        let lng = y.atan2(x).to_degrees();
+        (T::from_f64(lat), T::from_f64(lng))
+    }
+}
+
+/// (lat, lng) in degrees as a point on the unit sphere
+fn unit_vector<T: LatLngType>((lat, lng): (T, T)) -> (f64, f64, f64) {
+    let (lat, lng) = (lat.to_f64().to_radians(), lng.to_f64().to_radians());
+    (lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin())
+}
+
+/// running weighted sums needed to derive a centroid without holding on to the children,
+/// lets the clustering loops work on labels instead of cloned data points
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CentroidAccumulator<T: LatLngType> {
+    count: usize,
+    weight: f64,
+    first: Option<(T, T)>,
+    all_same: bool,
+    sum_lat: T,
+    sum_lng: T,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl<T: LatLngType> Default for CentroidAccumulator<T> {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            weight: 0.0,
+            first: None,
+            all_same: true,
+            sum_lat: T::from_usize(0),
+            sum_lng: T::from_usize(0),
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+}
+
+impl<T: LatLngType> CentroidAccumulator<T> {
+    pub(crate) fn push(&mut self, (lat, lng): (T, T), weight: f64) {
+        match self.first {
+            None => self.first = Some((lat, lng)),
+            Some(first) => self.all_same &= first == (lat, lng),
+        }
+        self.count += 1;
+        self.weight += weight;
+        self.sum_lat += lat * T::from_f64(weight);
+        self.sum_lng += lng * T::from_f64(weight);
+        let (x, y, z) = unit_vector((lat, lng));
+        self.x += x * weight;
+        self.y += y * weight;
+        self.z += z * weight;
+    }
+
+    /// add the points pushed into `other`
+    pub(crate) fn merge(&mut self, other: &Self) {
+        let Some(other_first) = other.first else {
+            return;
+        };
+        match self.first {
+            None => {
+                self.first = other.first;
+                self.all_same = other.all_same;
+            }
+            Some(first) => self.all_same &= other.all_same && first == other_first,
+        }
+        self.count += other.count;
+        self.weight += other.weight;
+        self.sum_lat += other.sum_lat;
+        self.sum_lng += other.sum_lng;
+        self.x += other.x;
+        self.y += other.y;
+        self.z += other.z;
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    /// summed weight of the pushed points
+    pub(crate) fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// None when nothing was pushed
+    pub(crate) fn centroid(&self, method: CentroidMethod) -> Option<(T, T)> {
+        let first = self.first?;
+        Some(match method {
+            // a node sitting on a single site keeps that site exactly, the trig round trip
+            // would otherwise nudge it by a few ulps
+            CentroidMethod::Spherical if self.all_same => first,
+            CentroidMethod::Spherical => self.spherical_mean(),
+            CentroidMethod::Planar => self.planar_mean(),
+        })
+    }
+
+    fn spherical_mean(&self) -> (T, T) {
+        let (x, y, z) = (self.x, self.y, self.z);
+        let length = (x * x + y * y + z * z).sqrt() / self.weight;
+        if length < DEGENERATE_VECTOR_LENGTH {
+            return self.planar_mean();
+        }
+
+        let lat = z.atan2(x.hypot(y)).to_degrees();
+        let lng = y.atan2(x).to_degrees();
+        (T::from_f64(lat), T::from_f64(lng))
+    }
+
+    fn planar_mean(&self) -> (T, T) {
+        let weight = T::from_f64(self.weight);
+        (self.sum_lat / weight, self.sum_lng / weight)
+    }
+}
+
 /// a node holds a centroid location and a list of user provided data points
-/// it also marks the total distance of the children to the centroid
+/// it also marks the total distance of the children to the centroid, each distance
+/// multiplied by the child's [UserDataType::weight]
 ///
 /// [DATAPOINT] must implement [UserDataType] and [T] must implement [LatLngType]
 #[derive(Clone, Debug)]
@@ -32,18 +205,26 @@ where
         self.children.push(data_point.clone());
     }
 
-    pub fn calculate_new_centroid(&mut self) -> (T, T) {
-        let (sum_lat, sum_lon) = self.children.iter().map(|child| child.get_coords()).fold(
-            (T::from_usize(0), T::from_usize(0)),
-            |(acc_lat, acc_lon), (lat, lon)| (acc_lat + lat, acc_lon + lon),
-        );
+    /// weighted centroid of the children using [CentroidMethod::Spherical],
+    /// None when the node has no children
+    pub fn calculate_new_centroid(&mut self) -> Option<(T, T)> {
+        self.calculate_centroid(CentroidMethod::default())
+    }
 
-        let count = T::from_usize(self.children.len());
+    pub fn calculate_centroid(&self, method: CentroidMethod) -> Option<(T, T)> {
+        let mut accumulator = CentroidAccumulator::default();
+        for child in self.children.iter() {
+            accumulator.push(child.get_coords(), child.weight());
+        }
+        accumulator.centroid(method)
+    }
 
-        let mean_lat = sum_lat / count;
-        let mean_lon = sum_lon / count;
+    pub fn calculate_spherical_centroid(&self) -> Option<(T, T)> {
+        self.calculate_centroid(CentroidMethod::Spherical)
+    }
 
-        (mean_lat, mean_lon)
+    pub fn calculate_planar_centroid(&self) -> Option<(T, T)> {
+        self.calculate_centroid(CentroidMethod::Planar)
     }
 
     pub fn has_only_unique_pairs(&self) -> bool {
@@ -0,0 +1,253 @@
+use crate::distance::{DistanceMetric, Haversine};
+use crate::error::ClusterError;
+use crate::kmeans::{self, closest_centroid, FittedKMeans, KMeansModel};
+use crate::nodes::CentroidMethod;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::{validate_data_points, ConvergenceReport};
+
+/// sequential k-means over points that arrive one at a time
+///
+/// the first k distinct points become the centroids, after that every point moves its
+/// closest centroid towards it by w / n, w being the point's weight and n the (decayed)
+/// weight the centroid has absorbed. with a decay below 1 old points are gradually forgotten and the
+/// centroids follow data that drifts over time
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::online::OnlineKMeans;
+///
+/// let mut online = OnlineKMeans::new(2).decay(0.99);
+/// for ping in [(40.71, -74.00), (51.50, -0.12), (40.72, -74.01), (51.51, -0.13)] {
+///     online.update(&ping).unwrap();
+/// }
+/// assert_eq!(online.centroids().len(), 2);
+/// assert_eq!(online.predict(&(40.70, -73.99)), online.predict(&(40.71, -74.00)));
+///
+/// let recent = [(40.73, -74.02), (51.52, -0.11)];
+/// let cluster = online.snapshot(&recent).unwrap().cluster(&recent).unwrap();
+/// assert_eq!(cluster.nodes[cluster.labels[0]].children.len(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct OnlineKMeans<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    k: usize,
+    decay: f64,
+    centroid: CentroidMethod,
+    metric: M,
+    centroids: Vec<(T, T)>,
+    /// decayed weight of the points behind each centroid, drives the learning rate
+    weights: Vec<f64>,
+    sizes: Vec<usize>,
+    total_distances: Vec<T>,
+    points_seen: usize,
+    last_shift: T,
+}
+
+impl<T: LatLngType> OnlineKMeans<T, Haversine> {
+    /// k clusters without decay
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            decay: 1.0,
+            centroid: CentroidMethod::default(),
+            metric: Haversine::default(),
+            centroids: Vec::with_capacity(k),
+            weights: Vec::with_capacity(k),
+            sizes: Vec::with_capacity(k),
+            total_distances: Vec::with_capacity(k),
+            points_seen: 0,
+            last_shift: T::default(),
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> OnlineKMeans<T, M> {
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// share of a centroid's history kept on each update, in (0, 1]. 1 weighs every
+    /// point equally, 0.99 roughly tracks the last hundred points of each centroid
+    pub fn decay(mut self, decay: f64) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    /// how a centroid moves towards a point, see [CentroidMethod]
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = centroid;
+        self
+    }
+
+    /// swap the distance metric, only possible before the first update since the learned
+    /// centroids and distances belong to the old one
+    pub fn metric<M2: DistanceMetric<T>>(
+        self,
+        metric: M2,
+    ) -> Result<OnlineKMeans<T, M2>, ClusterError> {
+        if self.points_seen > 0 {
+            return Err(ClusterError::MetricAfterUpdate {
+                points_seen: self.points_seen,
+            });
+        }
+        Ok(OnlineKMeans {
+            k: self.k,
+            decay: self.decay,
+            centroid: self.centroid,
+            metric,
+            centroids: Vec::with_capacity(self.k),
+            weights: Vec::with_capacity(self.k),
+            sizes: Vec::with_capacity(self.k),
+            total_distances: Vec::with_capacity(self.k),
+            points_seen: 0,
+            last_shift: T::default(),
+        })
+    }
+
+    /// current centroids, fewer than k until k distinct points were seen
+    pub fn centroids(&self) -> &[(T, T)] {
+        &self.centroids
+    }
+
+    /// number of points absorbed by each centroid, ignoring the decay
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    pub fn points_seen(&self) -> usize {
+        self.points_seen
+    }
+
+    /// absorb one point and return the index of the centroid it went to
+    ///
+    /// the point is rejected without touching the state if its coordinates are invalid,
+    /// the index in the rejection is [OnlineKMeans::points_seen]
+    pub fn update<P: UserDataType<T>>(&mut self, data_point: &P) -> Result<usize, ClusterError> {
+        self.check_settings()?;
+        validate_point(self.points_seen, data_point, &self.metric)?;
+        Ok(self.absorb(data_point.get_coords(), data_point.weight()))
+    }
+
+    /// absorb the points in order, nothing is absorbed if any of them is invalid. the
+    /// index in a rejection is the position in `data_points`
+    pub fn update_batch<P: UserDataType<T>>(
+        &mut self,
+        data_points: &[P],
+    ) -> Result<Vec<usize>, ClusterError> {
+        self.check_settings()?;
+        for (offset, data_point) in data_points.iter().enumerate() {
+            validate_point(offset, data_point, &self.metric)?;
+        }
+        Ok(data_points
+            .iter()
+            .map(|data_point| self.absorb(data_point.get_coords(), data_point.weight()))
+            .collect())
+    }
+
+    /// index of the closest current centroid, 0 before the first update
+    pub fn predict<P: UserDataType<T>>(&self, data_point: &P) -> usize {
+        closest_centroid(&data_point.get_coords(), &self.centroids, &self.metric).0
+    }
+
+    /// assign `data_points` to the current centroids without moving them, e.g. the
+    /// points of the last hour, materialize the nodes with [FittedKMeans::cluster]
+    pub fn snapshot<P: UserDataType<T>>(
+        &self,
+        data_points: &[P],
+    ) -> Result<FittedKMeans<T, M>, ClusterError>
+    where
+        M: Clone,
+    {
+        if self.centroids.is_empty() {
+            return Err(ClusterError::EmptyInput);
+        }
+        validate_data_points(1, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        Ok(kmeans::fitted_from_centroids(
+            &coords,
+            &weights,
+            self.centroids.clone(),
+            &self.metric,
+            self.report(),
+        ))
+    }
+
+    /// the current state as a model
+    ///
+    /// sizes count every absorbed point and total distances add up the weighted distance of
+    /// each point to its centroid at the time it was absorbed, the report counts updates as
+    /// iterations and holds the shift of the latest update
+    pub fn model(&self) -> KMeansModel<T, M>
+    where
+        M: Clone,
+    {
+        KMeansModel {
+            centroids: self.centroids.clone(),
+            sizes: self.sizes.clone(),
+            total_distances: self.total_distances.clone(),
+            inertia: self
+                .total_distances
+                .iter()
+                .fold(T::default(), |acc, &total| acc + total),
+            report: self.report(),
+            metric: self.metric.clone(),
+        }
+    }
+
+    fn report(&self) -> ConvergenceReport<T> {
+        ConvergenceReport {
+            iterations: self.points_seen,
+            converged: false,
+            max_centroid_shift: self.last_shift,
+        }
+    }
+
+    fn check_settings(&self) -> Result<(), ClusterError> {
+        if self.k == 0 {
+            return Err(ClusterError::ZeroClusters);
+        }
+        if !(self.decay > 0.0 && self.decay <= 1.0) {
+            return Err(ClusterError::InvalidDecay { decay: self.decay });
+        }
+        Ok(())
+    }
+
+    fn absorb(&mut self, coords: (T, T), weight: f64) -> usize {
+        self.points_seen += 1;
+        if self.centroids.len() < self.k && !self.centroids.contains(&coords) {
+            self.centroids.push(coords);
+            self.weights.push(weight);
+            self.sizes.push(1);
+            self.total_distances.push(T::default());
+            self.last_shift = T::default();
+            return self.centroids.len() - 1;
+        }
+        let (label, distance) = closest_centroid(&coords, &self.centroids, &self.metric);
+        self.weights[label] = self.weights[label] * self.decay + weight;
+        self.sizes[label] += 1;
+        self.total_distances[label] += distance * T::from_f64(weight);
+        let moved = self
+            .centroid
+            .step(self.centroids[label], coords, weight / self.weights[label]);
+        self.last_shift = self.metric.distance(&self.centroids[label], &moved);
+        self.centroids[label] = moved;
+        label
+    }
+}
+
+/// the per point part of [validate_data_points]
+fn validate_point<T, P, M>(index: usize, data_point: &P, metric: &M) -> Result<(), ClusterError>
+where
+    T: LatLngType,
+    P: UserDataType<T>,
+    M: DistanceMetric<T>,
+{
+    validate_data_points(1, std::slice::from_ref(data_point), metric).map_err(|error| match error {
+        ClusterError::NonFiniteCoordinate { .. } => ClusterError::NonFiniteCoordinate { index },
+        ClusterError::CoordinateOutOfRange { lat, lng, .. } => {
+            ClusterError::CoordinateOutOfRange { index, lat, lng }
+        }
+        ClusterError::InvalidWeight { weight, .. } => ClusterError::InvalidWeight { index, weight },
+        other => other,
+    })
+}
@@ -0,0 +1,474 @@
+use crate::dbscan::{validate_density_parameters, FittedDbscan};
+use crate::density::{core_distance, density_clusters, DensityClusters, NeighborIndex};
+use crate::distance::{DistanceMetric, DistanceUnit, Haversine};
+use crate::error::ClusterError;
+use crate::nodes::CentroidMethod;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::ops::RangeInclusive;
+
+/// builder for OPTICS, an ordering of the data points by density that can be cut into
+/// clusters afterwards without measuring anything again
+///
+/// the data points are visited so that each next one is the closest (in reachability)
+/// to the ones already visited. plotting the reachability distances in that order shows
+/// clusters as valleys, [FittedOptics::extract_dbscan] cuts the plot at a fixed height
+/// and [FittedOptics::extract_xi] finds the valleys by their steep edges
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{Haversine, Units};
+/// use coordinate_cluster::optics::Optics;
+///
+/// let mut pings = vec![];
+/// for i in 0..10 {
+///     pings.push((40.7128 + i as f64 * 0.0001, -74.0060));
+///     pings.push((51.5074 + i as f64 * 0.0001, -0.1278));
+/// }
+/// let fitted = Optics::new(4)
+///     .metric(Haversine::new(Units::kilometers()))
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(fitted.ordering.len(), pings.len());
+/// assert_eq!(fitted.extract_dbscan(1.0).unwrap().cluster_count, 2);
+/// assert_eq!(fitted.extract_dbscan(10_000.0).unwrap().cluster_count, 1);
+/// assert_eq!(fitted.extract_xi(0.05).unwrap().cluster_count, 2);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Optics<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    min_points: usize,
+    max_eps: Option<T>,
+    centroid: Option<CentroidMethod>,
+    metric: M,
+}
+
+impl<T: LatLngType> Optics<T, Haversine> {
+    /// a data point is core within the radius its neighbours (itself included) weigh
+    /// `min_points` in, with the default weight of 1 that is its `min_points`th neighbour
+    pub fn new(min_points: usize) -> Self {
+        Self {
+            min_points,
+            max_eps: None,
+            centroid: None,
+            metric: Haversine::default(),
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T>> Optics<T, M> {
+    /// largest radius searched around each data point, in the units of the metric.
+    /// unbounded by default, a bound makes the run cheaper but caps the eps that can be
+    /// extracted later
+    pub fn max_eps(mut self, max_eps: T) -> Self {
+        self.max_eps = Some(max_eps);
+        self
+    }
+
+    /// how the node locations of the extracted clusters are found, defaults to
+    /// [CentroidMethod::Spherical] for lat/lng metrics and [CentroidMethod::Planar] otherwise
+    pub fn centroid(mut self, centroid: CentroidMethod) -> Self {
+        self.centroid = Some(centroid);
+        self
+    }
+
+    /// swap the distance metric, `max_eps` is now read in its units
+    pub fn metric<M2: DistanceMetric<T>>(self, metric: M2) -> Optics<T, M2> {
+        Optics {
+            min_points: self.min_points,
+            max_eps: self.max_eps,
+            centroid: self.centroid,
+            metric,
+        }
+    }
+
+    pub fn fit<DATAPOINT>(
+        &self,
+        data_points: &[DATAPOINT],
+    ) -> Result<FittedOptics<T, M>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+        M: Clone,
+    {
+        let max_eps = self.max_eps.unwrap_or(T::max_value());
+        validate_density_parameters(max_eps, self.min_points)?;
+        validate_data_points(1, data_points, &self.metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let index = NeighborIndex::new(&coords, &self.metric);
+        let n = coords.len();
+
+        let mut ordering = Vec::with_capacity(n);
+        let mut reachability: Vec<Option<T>> = vec![None; n];
+        let mut core_distances: Vec<Option<T>> = vec![None; n];
+        let mut predecessors: Vec<Option<usize>> = vec![None; n];
+        let mut processed = vec![false; n];
+        let mut neighbors = vec![];
+        let mut seeds = BinaryHeap::new();
+        for start in 0..n {
+            if processed[start] {
+                continue;
+            }
+            seeds.push(Seed {
+                reachability: f64::INFINITY,
+                index: start,
+            });
+            while let Some(Seed { index: point, .. }) = seeds.pop() {
+                if processed[point] {
+                    continue;
+                }
+                processed[point] = true;
+                ordering.push(point);
+                index.within(point, max_eps, &mut neighbors);
+                let Some(core_distance) =
+                    core_distance(&mut neighbors, &weights, self.min_points as f64)
+                else {
+                    continue;
+                };
+                core_distances[point] = Some(core_distance);
+                for &(other, distance) in neighbors.iter() {
+                    if processed[other] {
+                        continue;
+                    }
+                    let reach = if distance > core_distance {
+                        distance
+                    } else {
+                        core_distance
+                    };
+                    if reachability[other].is_none_or(|current| reach < current) {
+                        reachability[other] = Some(reach);
+                        predecessors[other] = Some(point);
+                        seeds.push(Seed {
+                            reachability: reach.to_f64(),
+                            index: other,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(FittedOptics {
+            ordering,
+            reachability,
+            core_distances,
+            predecessors,
+            min_points: self.min_points,
+            max_eps,
+            centroid: self
+                .centroid
+                .unwrap_or_else(|| CentroidMethod::for_metric(&self.metric)),
+            metric: self.metric.clone(),
+        })
+    }
+}
+
+/// a data point waiting to be visited, the heap pops the lowest reachability first and
+/// the lowest index among equal reachabilities
+#[derive(PartialEq)]
+struct Seed {
+    reachability: f64,
+    index: usize,
+}
+
+impl Eq for Seed {}
+
+impl Ord for Seed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .reachability
+            .total_cmp(&self.reachability)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Seed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// result of [Optics::fit], every per point vector is in input order
+#[derive(Clone, Debug)]
+pub struct FittedOptics<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    /// data point indices in the order they were visited
+    pub ordering: Vec<usize>,
+    /// smallest reachability distance of each data point from the ones visited before
+    /// it, None for the first point of every connected region
+    pub reachability: Vec<Option<T>>,
+    /// distance to the `min_points`th nearest data point, None when fewer than
+    /// `min_points` data points are within `max_eps`
+    pub core_distances: Vec<Option<T>>,
+    /// data point each one was reached from
+    pub predecessors: Vec<Option<usize>>,
+    pub min_points: usize,
+    pub max_eps: T,
+    /// how the node locations of the extracted clusters are found
+    pub centroid: CentroidMethod,
+    pub metric: M,
+}
+
+/// clusters found by [FittedOptics::extract_xi]
+#[derive(Clone, Debug)]
+pub struct XiClusters<M = Haversine> {
+    /// cluster of each data point in input order, None for noise. a data point in
+    /// nested clusters gets the innermost one
+    pub labels: Vec<Option<usize>>,
+    pub cluster_count: usize,
+    /// every cluster found as positions in [FittedOptics::ordering], nested ones
+    /// before the ones that contain them. this is the full hierarchy, `labels` only
+    /// holds the clusters that do not contain another one
+    pub ranges: Vec<RangeInclusive<usize>>,
+    /// how the node locations of the clusters are found
+    pub centroid: CentroidMethod,
+    pub metric: M,
+}
+
+impl<M> XiClusters<M> {
+    /// build the nodes and the noise set over the data points that were fitted
+    pub fn clusters<'a, T, DATAPOINT>(
+        &self,
+        data_points: &'a [DATAPOINT],
+    ) -> Result<DensityClusters<T, &'a DATAPOINT>, ClusterError>
+    where
+        T: LatLngType,
+        DATAPOINT: UserDataType<T>,
+        M: DistanceMetric<T>,
+    {
+        density_clusters(
+            &self.labels,
+            self.cluster_count,
+            data_points,
+            &self.metric,
+            self.centroid,
+        )
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T> + Clone> FittedOptics<T, M> {
+    pub fn unit(&self) -> Option<DistanceUnit> {
+        self.metric.unit()
+    }
+
+    /// reachability distances in visiting order, the reachability plot
+    pub fn reachability_plot(&self) -> Vec<Option<T>> {
+        self.ordering
+            .iter()
+            .map(|&point| self.reachability[point])
+            .collect()
+    }
+
+    /// the clustering DBSCAN gives for `eps`, which must not exceed `max_eps`
+    ///
+    /// core points are clustered exactly like DBSCAN does, a border point is noise when it
+    /// was first reached from a point that is not core at `eps` and may end up in another
+    /// cluster when it is within `eps` of two
+    pub fn extract_dbscan(&self, eps: T) -> Result<FittedDbscan<T, M>, ClusterError> {
+        validate_density_parameters(eps, self.min_points)?;
+        if eps > self.max_eps {
+            return Err(ClusterError::InvalidEps { eps: eps.to_f64() });
+        }
+        let n = self.ordering.len();
+        let mut labels = vec![None; n];
+        let core: Vec<bool> = self
+            .core_distances
+            .iter()
+            .map(|core| core.is_some_and(|core| core <= eps))
+            .collect();
+        let mut cluster_count = 0;
+        for &point in self.ordering.iter() {
+            if self.reachability[point].is_none_or(|reach| reach > eps) {
+                if core[point] {
+                    cluster_count += 1;
+                    labels[point] = Some(cluster_count - 1);
+                }
+            } else if cluster_count > 0 {
+                labels[point] = Some(cluster_count - 1);
+            }
+        }
+        Ok(FittedDbscan {
+            labels,
+            core,
+            cluster_count,
+            eps,
+            centroid: self.centroid,
+            metric: self.metric.clone(),
+        })
+    }
+
+    /// clusters as valleys of the reachability plot bounded by a drop and a rise of at
+    /// least the fraction `xi`, in (0, 1). clusters need at least `min_points` points
+    pub fn extract_xi(&self, xi: f64) -> Result<XiClusters<M>, ClusterError> {
+        if !(xi > 0.0 && xi < 1.0) {
+            return Err(ClusterError::InvalidXi { xi });
+        }
+        let plot: Vec<f64> = self
+            .reachability_plot()
+            .into_iter()
+            .map(|reach| reach.map_or(f64::INFINITY, |reach| reach.to_f64()))
+            .collect();
+        let predecessors: Vec<Option<usize>> = self
+            .ordering
+            .iter()
+            .map(|&point| self.predecessors[point])
+            .collect();
+        let ranges = xi_clusters(&plot, &predecessors, &self.ordering, xi, self.min_points);
+
+        // a cluster only gets a label when none of its points has one yet, the nested
+        // ones come first so those win
+        let mut in_order: Vec<Option<usize>> = vec![None; self.ordering.len()];
+        let mut cluster_count = 0;
+        for range in ranges.iter() {
+            if in_order[range.clone()].iter().all(Option::is_none) {
+                in_order[range.clone()].fill(Some(cluster_count));
+                cluster_count += 1;
+            }
+        }
+        let mut labels = vec![None; self.ordering.len()];
+        for (&point, label) in self.ordering.iter().zip(in_order) {
+            labels[point] = label;
+        }
+        Ok(XiClusters {
+            labels,
+            cluster_count,
+            ranges,
+            centroid: self.centroid,
+            metric: self.metric.clone(),
+        })
+    }
+}
+
+/// a steep down area of the reachability plot and the highest point seen since it
+struct SteepDownArea {
+    start: usize,
+    end: usize,
+    mib: f64,
+}
+
+/// the xi steep area method of Ankerst et al., with the predecessor correction and the
+/// fixed criterion 4c of the scikit-learn implementation
+fn xi_clusters(
+    plot: &[f64],
+    predecessors: &[Option<usize>],
+    ordering: &[usize],
+    xi: f64,
+    min_points: usize,
+) -> Vec<RangeInclusive<usize>> {
+    let n = plot.len();
+    // one past the end reads as infinitely high so a trailing valley can close
+    let reach = |index: usize| plot.get(index).copied().unwrap_or(f64::INFINITY);
+    let xi_complement = 1.0 - xi;
+    let ratio: Vec<f64> = (0..n).map(|i| reach(i) / reach(i + 1)).collect();
+    let steep_up: Vec<bool> = ratio.iter().map(|&r| r <= xi_complement).collect();
+    let steep_down: Vec<bool> = ratio.iter().map(|&r| r >= 1.0 / xi_complement).collect();
+    let down: Vec<bool> = ratio.iter().map(|&r| r > 1.0).collect();
+    let up: Vec<bool> = ratio.iter().map(|&r| r < 1.0).collect();
+
+    let mut areas: Vec<SteepDownArea> = vec![];
+    let mut clusters = vec![];
+    let mut index = 0;
+    let mut mib: f64 = 0.0;
+    for steep in 0..n {
+        if !(steep_up[steep] || steep_down[steep]) || steep < index {
+            continue;
+        }
+        mib = (index..=steep).map(reach).fold(mib, f64::max);
+        // drop the areas the plot has since risen above, the rest remember the peak
+        if mib.is_infinite() {
+            areas.clear();
+        } else {
+            areas.retain(|area| mib <= reach(area.start) * xi_complement);
+            for area in areas.iter_mut() {
+                area.mib = area.mib.max(mib);
+            }
+        }
+
+        if steep_down[steep] {
+            let end = extend_region(&steep_down, &up, steep, min_points);
+            areas.push(SteepDownArea {
+                start: steep,
+                end,
+                mib: 0.0,
+            });
+            index = end + 1;
+            mib = reach(index);
+            continue;
+        }
+
+        let up_start = steep;
+        let up_end = extend_region(&steep_up, &down, up_start, min_points);
+        index = up_end + 1;
+        mib = reach(index);
+        let mut found = vec![];
+        for area in areas.iter() {
+            let mut start = area.start;
+            let mut end = up_end;
+            if reach(end + 1) * xi_complement < area.mib {
+                continue;
+            }
+            let area_max = reach(area.start);
+            if area_max * xi_complement >= reach(end + 1) {
+                while reach(start + 1) > reach(end + 1) && start < area.end {
+                    start += 1;
+                }
+            } else if reach(end + 1) * xi_complement >= area_max {
+                while end > up_start && reach(end - 1) > area_max {
+                    end -= 1;
+                }
+            }
+            let Some((start, end)) = correct_predecessor(plot, predecessors, ordering, start, end)
+            else {
+                continue;
+            };
+            if end - start + 1 < min_points || start > area.end || end < up_start {
+                continue;
+            }
+            found.push(start..=end);
+        }
+        // the last area matched is the closest one, i.e. the smallest cluster
+        clusters.extend(found.into_iter().rev());
+    }
+    clusters
+}
+
+/// end of the steep region starting at `start`, allowing at most `min_points`
+/// consecutive points that are neither steep nor going the other way
+fn extend_region(steep: &[bool], other_way: &[bool], start: usize, min_points: usize) -> usize {
+    let mut flat = 0;
+    let mut end = start;
+    for index in start..steep.len() {
+        if steep[index] {
+            flat = 0;
+            end = index;
+        } else if !other_way[index] {
+            flat += 1;
+            if flat > min_points {
+                break;
+            }
+        } else {
+            return end;
+        }
+    }
+    end
+}
+
+/// shrink the end of a cluster until its last point was reached from inside it
+fn correct_predecessor(
+    plot: &[f64],
+    predecessors: &[Option<usize>],
+    ordering: &[usize],
+    start: usize,
+    mut end: usize,
+) -> Option<(usize, usize)> {
+    while start < end {
+        if plot[start] > plot[end] {
+            return Some((start, end));
+        }
+        if let Some(predecessor) = predecessors[end] {
+            if ordering[start..end].contains(&predecessor) {
+                return Some((start, end));
+            }
+        }
+        end -= 1;
+    }
+    None
+}
@@ -0,0 +1,254 @@
+//! the per point work of the clustering loops, spread over a rayon pool when the
+//! `parallel` feature is enabled
+//!
+//! sums are taken over fixed size chunks and the partial sums merged in chunk order,
+//! so the serial and parallel builds give bit for bit the same result for a given seed
+use crate::distance::DistanceMetric;
+use crate::kmeans::{closest_centroid, Run};
+use crate::nodes::CentroidAccumulator;
+use crate::user_data::LatLngType;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// number of points summed together before merging, independent of the thread count
+const CHUNK_SIZE: usize = 1024;
+
+/// [Sync] when the `parallel` feature is enabled, implemented by every type otherwise
+///
+/// metrics have to be shared between the worker threads, this keeps that requirement
+/// out of the serial build
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "parallel")]
+impl<S: Sync + ?Sized> MaybeSync for S {}
+
+/// [Sync] when the `parallel` feature is enabled, implemented by every type otherwise
+///
+/// metrics have to be shared between the worker threads, this keeps that requirement
+/// out of the serial build
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "parallel"))]
+impl<S: ?Sized> MaybeSync for S {}
+
+/// write the closest centroid and the distance to it for every coordinate
+pub(crate) fn assign<T, M>(
+    coords: &[(T, T)],
+    centroids: &[(T, T)],
+    metric: &M,
+    labels: &mut [usize],
+    distances: &mut [T],
+) where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let assign_one = |((coord, label), distance): ((&(T, T), &mut usize), &mut T)| {
+        (*label, *distance) = closest_centroid(coord, centroids, metric);
+    };
+    #[cfg(feature = "parallel")]
+    coords
+        .par_iter()
+        .zip(labels.par_iter_mut())
+        .zip(distances.par_iter_mut())
+        .for_each(assign_one);
+    #[cfg(not(feature = "parallel"))]
+    coords
+        .iter()
+        .zip(labels.iter_mut())
+        .zip(distances.iter_mut())
+        .for_each(assign_one);
+}
+
+/// distance of every coordinate to every centroid, row major by coordinate
+pub(crate) fn centroid_distances<T, M>(
+    coords: &[(T, T)],
+    centroids: &[(T, T)],
+    metric: &M,
+) -> Vec<T>
+where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let k = centroids.len();
+    let mut distances = vec![T::default(); coords.len() * k];
+    let measure_row = |(coord, row): (&(T, T), &mut [T])| {
+        for (distance, centroid) in row.iter_mut().zip(centroids) {
+            *distance = metric.distance(centroid, coord);
+        }
+    };
+    #[cfg(feature = "parallel")]
+    coords
+        .par_iter()
+        .zip(distances.par_chunks_mut(k))
+        .for_each(measure_row);
+    #[cfg(not(feature = "parallel"))]
+    coords
+        .iter()
+        .zip(distances.chunks_mut(k))
+        .for_each(measure_row);
+    distances
+}
+
+/// distance of every coordinate to the centroid it is labelled with
+pub(crate) fn measure<T, M>(
+    coords: &[(T, T)],
+    centroids: &[(T, T)],
+    metric: &M,
+    labels: &[usize],
+    distances: &mut [T],
+) where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let measure_one = |((coord, &label), distance): ((&(T, T), &usize), &mut T)| {
+        *distance = metric.distance(&centroids[label], coord);
+    };
+    #[cfg(feature = "parallel")]
+    coords
+        .par_iter()
+        .zip(labels.par_iter())
+        .zip(distances.par_iter_mut())
+        .for_each(measure_one);
+    #[cfg(not(feature = "parallel"))]
+    coords
+        .iter()
+        .zip(labels.iter())
+        .zip(distances.iter_mut())
+        .for_each(measure_one);
+}
+
+/// centroid accumulator and weighted total distance of each of the `k` nodes given the
+/// labels
+pub(crate) fn accumulate<T: LatLngType>(
+    k: usize,
+    coords: &[(T, T)],
+    weights: &[f64],
+    labels: &[usize],
+    distances: &[T],
+) -> (Vec<CentroidAccumulator<T>>, Vec<T>) {
+    let accumulate_chunk = |chunk: usize| {
+        let range = chunk * CHUNK_SIZE..((chunk + 1) * CHUNK_SIZE).min(coords.len());
+        let mut accumulators = vec![CentroidAccumulator::default(); k];
+        let mut total_distances = vec![T::default(); k];
+        for index in range {
+            accumulators[labels[index]].push(coords[index], weights[index]);
+            total_distances[labels[index]] += distances[index] * T::from_f64(weights[index]);
+        }
+        (accumulators, total_distances)
+    };
+    let chunks = coords.len().div_ceil(CHUNK_SIZE);
+    #[cfg(feature = "parallel")]
+    let partials: Vec<_> = (0..chunks).into_par_iter().map(accumulate_chunk).collect();
+    #[cfg(not(feature = "parallel"))]
+    let partials: Vec<_> = (0..chunks).map(accumulate_chunk).collect();
+
+    let mut accumulators = vec![CentroidAccumulator::default(); k];
+    let mut total_distances = vec![T::default(); k];
+    for (chunk_accumulators, chunk_distances) in partials {
+        for (accumulator, chunk) in accumulators.iter_mut().zip(&chunk_accumulators) {
+            accumulator.merge(chunk);
+        }
+        for (total, chunk) in total_distances.iter_mut().zip(chunk_distances) {
+            *total += chunk;
+        }
+    }
+    (accumulators, total_distances)
+}
+
+/// every pairwise distance as f64, row major. each pair is measured once so the matrix
+/// is exactly symmetric
+pub(crate) fn distance_matrix<T, M>(coords: &[(T, T)], metric: &M) -> Vec<f64>
+where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let n = coords.len();
+    let row = |i: usize| -> Vec<f64> {
+        coords[i + 1..]
+            .iter()
+            .map(|other| metric.distance(&coords[i], other).to_f64())
+            .collect()
+    };
+    #[cfg(feature = "parallel")]
+    let upper: Vec<Vec<f64>> = (0..n).into_par_iter().map(row).collect();
+    #[cfg(not(feature = "parallel"))]
+    let upper: Vec<Vec<f64>> = (0..n).map(row).collect();
+
+    let mut matrix = vec![0.0; n * n];
+    for (i, row) in upper.into_iter().enumerate() {
+        for (offset, distance) in row.into_iter().enumerate() {
+            let j = i + 1 + offset;
+            matrix[i * n + j] = distance;
+            matrix[j * n + i] = distance;
+        }
+    }
+    matrix
+}
+
+/// silhouette of every point given its label and the distance between two points by
+/// index, a point alone in its cluster scores 0. the mean distances to a cluster are
+/// weighted by the points' weights
+pub(crate) fn silhouette<F>(labels: &[usize], weights: &[f64], k: usize, distance: F) -> Vec<f64>
+where
+    F: Fn(usize, usize) -> f64 + MaybeSync,
+{
+    let mut sizes = vec![0usize; k];
+    let mut masses = vec![0.0; k];
+    for (&label, &weight) in labels.iter().zip(weights) {
+        sizes[label] += 1;
+        masses[label] += weight;
+    }
+    let silhouette_one = |point: usize| {
+        let own = labels[point];
+        if sizes[own] < 2 {
+            return 0.0;
+        }
+        let mut sums = vec![0.0; k];
+        for (other, &label) in labels.iter().enumerate() {
+            if other != point {
+                sums[label] += weights[other] * distance(point, other);
+            }
+        }
+        let a = sums[own] / (masses[own] - weights[point]);
+        let b = (0..k)
+            .filter(|&cluster| cluster != own && sizes[cluster] > 0)
+            .map(|cluster| sums[cluster] / masses[cluster])
+            .fold(f64::INFINITY, f64::min);
+        match b.is_finite() && a.max(b) > 0.0 {
+            true => (b - a) / a.max(b),
+            false => 0.0,
+        }
+    };
+    #[cfg(feature = "parallel")]
+    let silhouettes = (0..labels.len())
+        .into_par_iter()
+        .map(silhouette_one)
+        .collect();
+    #[cfg(not(feature = "parallel"))]
+    let silhouettes = (0..labels.len()).map(silhouette_one).collect();
+    silhouettes
+}
+
+/// one k-means run per seed, the results in the order of the seeds
+pub(crate) fn restarts<T, F>(seeds: &[u64], run: F) -> Vec<Run<T>>
+where
+    T: LatLngType,
+    F: Fn(u64) -> Run<T> + MaybeSync,
+{
+    #[cfg(feature = "parallel")]
+    let runs = seeds.par_iter().map(|&seed| run(seed)).collect();
+    #[cfg(not(feature = "parallel"))]
+    let runs = seeds.iter().map(|&seed| run(seed)).collect();
+    runs
+}
+
+/// mean of `values` weighted by `weights`
+pub(crate) fn weighted_mean(values: &[f64], weights: &[f64]) -> f64 {
+    let total: f64 = weights.iter().sum();
+    values
+        .iter()
+        .zip(weights)
+        .map(|(value, weight)| value * weight)
+        .sum::<f64>()
+        / total
+}
@@ -0,0 +1,407 @@
+use crate::distance::{DistanceMetric, Haversine};
+use crate::error::ClusterError;
+use crate::kmeans::{FittedKMeans, KMeans};
+use crate::parallel;
+use crate::user_data::{LatLngType, UserDataType};
+use crate::validate_data_points;
+use std::ops::RangeInclusive;
+
+/// dimensions of a coordinate, for the parameter count of the BIC
+const DIMENSIONS: f64 = 2.0;
+
+/// which score picks [KSelection::recommended]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Criterion {
+    /// the k where the inertia curve bends the most, the point farthest below the chord
+    /// from the first to the last k. needs at least three k
+    Elbow,
+    /// the k with the highest mean silhouette. k = 1 has no silhouette, so this is only 1
+    /// when the range holds no larger k
+    #[default]
+    Silhouette,
+    /// the smallest k whose gap is within one standard error of the next one, as in
+    /// Tibshirani et al.
+    Gap,
+    /// X-means, split the clusters while that improves their BIC
+    XMeans,
+}
+
+/// scores of one k
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KScore<T: LatLngType> {
+    pub k: usize,
+    /// total distance of the data points to their centroids
+    pub inertia: T,
+    /// mean silhouette over all data points in [-1, 1], None for k = 1
+    pub silhouette: Option<f64>,
+    /// mean log inertia of the reference data minus the log inertia of the data
+    pub gap: f64,
+    /// standard error of the reference log inertia
+    pub gap_error: f64,
+    /// bayesian information criterion of the clusters as spherical gaussians, higher
+    /// is better
+    pub bic: f64,
+}
+
+/// result of [KSelector::fit]
+#[derive(Clone, Debug, PartialEq)]
+pub struct KSelection<T: LatLngType> {
+    /// the k chosen by the configured [Criterion]
+    pub recommended: usize,
+    pub criterion: Criterion,
+    /// one row per k in the range, ascending
+    pub scores: Vec<KScore<T>>,
+    /// the k each criterion picks on its own
+    pub elbow: usize,
+    pub silhouette: usize,
+    pub gap: usize,
+    pub xmeans: usize,
+}
+
+/// runs k-means over a range of k and scores every one of them
+///
+/// every k is fitted with the settings of the [KMeans] template. the silhouette holds an
+/// n * n distance matrix and the gap statistic fits `references` uniform datasets per k,
+/// so this is meant for up to a few thousand data points
+///
+/// Example:
+/// ```
+/// use coordinate_cluster::distance::{Haversine, Units};
+/// use coordinate_cluster::selection::{Criterion, KSelector};
+/// use coordinate_cluster::KMeans;
+///
+/// let mut rng = fastrand::Rng::with_seed(1);
+/// let mut pings = vec![];
+/// for (lat, lng) in [(40.7, -74.0), (34.0, -118.2), (41.9, -87.6)] {
+///     for _ in 0..30 {
+///         pings.push((lat + rng.f64() * 0.2, lng + rng.f64() * 0.2));
+///     }
+/// }
+/// let selection = KSelector::new(1..=6)
+///     .kmeans(KMeans::new(1).metric(Haversine::new(Units::kilometers())).seed(3))
+///     .criterion(Criterion::Silhouette)
+///     .fit(&pings)
+///     .unwrap();
+/// assert_eq!(selection.recommended, 3);
+/// assert_eq!(selection.scores.len(), 6);
+/// assert_eq!(selection.gap, 3);
+/// ```
+#[derive(Clone, Debug)]
+pub struct KSelector<T: LatLngType, M: DistanceMetric<T> = Haversine> {
+    range: RangeInclusive<usize>,
+    kmeans: KMeans<T, M>,
+    criterion: Criterion,
+    references: usize,
+}
+
+impl<T: LatLngType> KSelector<T, Haversine> {
+    /// score every k in `range` with the default [KMeans] and 10 gap references
+    pub fn new(range: RangeInclusive<usize>) -> Self {
+        Self {
+            range,
+            kmeans: KMeans::new(1),
+            criterion: Criterion::default(),
+            references: 10,
+        }
+    }
+}
+
+impl<T: LatLngType, M: DistanceMetric<T> + Clone> KSelector<T, M> {
+    /// settings every k is fitted with, its own k is ignored. set a seed on it for
+    /// reproducible scores, the gap references are drawn from the same seed
+    pub fn kmeans<M2: DistanceMetric<T>>(self, kmeans: KMeans<T, M2>) -> KSelector<T, M2> {
+        KSelector {
+            range: self.range,
+            kmeans,
+            criterion: self.criterion,
+            references: self.references,
+        }
+    }
+
+    pub fn criterion(mut self, criterion: Criterion) -> Self {
+        self.criterion = criterion;
+        self
+    }
+
+    /// number of uniform reference datasets of the gap statistic
+    pub fn references(mut self, references: usize) -> Self {
+        self.references = references;
+        self
+    }
+
+    pub fn fit<DATAPOINT>(&self, data_points: &[DATAPOINT]) -> Result<KSelection<T>, ClusterError>
+    where
+        DATAPOINT: UserDataType<T>,
+    {
+        let (min_k, max_k) = (*self.range.start(), *self.range.end());
+        if min_k == 0 {
+            return Err(ClusterError::ZeroClusters);
+        }
+        if min_k > max_k {
+            return Err(ClusterError::EmptyKRange { min_k, max_k });
+        }
+        if self.references == 0 {
+            return Err(ClusterError::ZeroRestarts);
+        }
+        validate_data_points(max_k, data_points, &self.kmeans.options().metric)?;
+        let coords: Vec<(T, T)> = data_points.iter().map(|dp| dp.get_coords()).collect();
+        let weights: Vec<f64> = data_points.iter().map(|dp| dp.weight()).collect();
+        let metric = &self.kmeans.options().metric;
+        let matrix = parallel::distance_matrix(&coords, metric);
+        let mut rng = self
+            .kmeans
+            .options()
+            .seed
+            .map(fastrand::Rng::with_seed)
+            .unwrap_or_default();
+        let references: Vec<Vec<Weighted<T>>> = (0..self.references)
+            .map(|_| uniform_reference(&coords, &weights, metric.expects_lat_lng(), &mut rng))
+            .collect();
+
+        let mut scores = Vec::with_capacity(max_k - min_k + 1);
+        for k in min_k..=max_k {
+            let fitted = self.fit_k(k, data_points)?;
+            let reference_logs = references
+                .iter()
+                .map(|reference| Ok(log_inertia(self.fit_k(k, reference)?.model.inertia)))
+                .collect::<Result<Vec<f64>, ClusterError>>()?;
+            let mean = reference_logs.iter().sum::<f64>() / self.references as f64;
+            let variance = reference_logs
+                .iter()
+                .map(|log| (log - mean).powi(2))
+                .sum::<f64>()
+                / self.references as f64;
+            scores.push(KScore {
+                k,
+                inertia: fitted.model.inertia,
+                silhouette: (k > 1).then(|| {
+                    let n = coords.len();
+                    let samples =
+                        parallel::silhouette(&fitted.labels, &weights, k, |a, b| matrix[a * n + b]);
+                    parallel::weighted_mean(&samples, &weights)
+                }),
+                gap: mean - log_inertia(fitted.model.inertia),
+                gap_error: variance.sqrt() * (1.0 + 1.0 / self.references as f64).sqrt(),
+                bic: bic(&fitted.labels, &fitted.distances, &weights, k),
+            });
+        }
+
+        let elbow = elbow(&scores);
+        let silhouette = scores
+            .iter()
+            .filter_map(|score| score.silhouette.map(|s| (score.k, s)))
+            .fold((min_k, f64::NEG_INFINITY), |best, (k, s)| {
+                if s > best.1 {
+                    (k, s)
+                } else {
+                    best
+                }
+            })
+            .0;
+        let gap = gap(&scores);
+        let xmeans = self.xmeans(data_points, min_k, max_k)?;
+        let recommended = match self.criterion {
+            Criterion::Elbow => elbow,
+            Criterion::Silhouette => silhouette,
+            Criterion::Gap => gap,
+            Criterion::XMeans => xmeans,
+        };
+        Ok(KSelection {
+            recommended,
+            criterion: self.criterion,
+            scores,
+            elbow,
+            silhouette,
+            gap,
+            xmeans,
+        })
+    }
+
+    fn fit_k<P: UserDataType<T>>(
+        &self,
+        k: usize,
+        data_points: &[P],
+    ) -> Result<FittedKMeans<T, M>, ClusterError> {
+        KMeans::with_options(k, self.kmeans.options().clone()).fit(data_points)
+    }
+
+    /// start at `min_k` and split every cluster whose two halves have a better BIC than
+    /// the cluster itself, refitting all clusters after each round, until nothing splits
+    /// or `max_k` is reached
+    fn xmeans<DATAPOINT: UserDataType<T>>(
+        &self,
+        data_points: &[DATAPOINT],
+        min_k: usize,
+        max_k: usize,
+    ) -> Result<usize, ClusterError> {
+        let mut k = min_k;
+        while k < max_k {
+            let fitted = self.fit_k(k, data_points)?;
+            let mut splits = 0;
+            for cluster in 0..k {
+                let members: Vec<&DATAPOINT> = data_points
+                    .iter()
+                    .zip(&fitted.labels)
+                    .filter(|(_, &label)| label == cluster)
+                    .map(|(data_point, _)| data_point)
+                    .collect();
+                let first = members.first().map(|member| member.get_coords());
+                if members.len() < 2
+                    || members
+                        .iter()
+                        .all(|member| Some(member.get_coords()) == first)
+                {
+                    continue;
+                }
+                let weights: Vec<f64> = members.iter().map(|member| member.weight()).collect();
+                let distances: Vec<T> = fitted
+                    .labels
+                    .iter()
+                    .zip(&fitted.distances)
+                    .filter(|(&label, _)| label == cluster)
+                    .map(|(_, &distance)| distance)
+                    .collect();
+                let parent = bic(&vec![0; members.len()], &distances, &weights, 1);
+                let halves = self.fit_k(2, &members)?;
+                let children = bic(&halves.labels, &halves.distances, &weights, 2);
+                if children > parent {
+                    splits += 1;
+                }
+            }
+            if splits == 0 {
+                break;
+            }
+            k = (k + splits).min(max_k);
+        }
+        Ok(k)
+    }
+}
+
+/// the log of an inertia, a perfect fit counts as the smallest positive value
+fn log_inertia<T: LatLngType>(inertia: T) -> f64 {
+    inertia.to_f64().max(f64::MIN_POSITIVE).ln()
+}
+
+/// a reference point, carrying the weight of the data point it stands in for
+#[derive(Clone, Copy, Debug)]
+struct Weighted<T: LatLngType> {
+    coords: (T, T),
+    weight: f64,
+}
+
+impl<T: LatLngType> UserDataType<T> for Weighted<T> {
+    fn get_coords(&self) -> (T, T) {
+        self.coords
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+}
+
+/// n random points spread uniformly by area over the bounding box of `coords`, on the
+/// sphere for lat/lng metrics and in the plane otherwise. the points take the weights of
+/// the data points in order, so the reference weighs as much as the data
+fn uniform_reference<T: LatLngType>(
+    coords: &[(T, T)],
+    weights: &[f64],
+    on_sphere: bool,
+    rng: &mut fastrand::Rng,
+) -> Vec<Weighted<T>> {
+    let ((lat_min, lat_max), (lng_min, lng_max)) =
+        crate::min_max(coords).expect("coords are not empty");
+    let (lat_min, lat_max) = (lat_min.to_f64(), lat_max.to_f64());
+    let (lng_min, lng_max) = (lng_min.to_f64(), lng_max.to_f64());
+    let (sin_min, sin_max) = (lat_min.to_radians().sin(), lat_max.to_radians().sin());
+    weights
+        .iter()
+        .map(|&weight| {
+            let lat = match on_sphere {
+                // uniform in sin(lat) is uniform in area
+                true => (sin_min + rng.f64() * (sin_max - sin_min))
+                    .clamp(-1.0, 1.0)
+                    .asin()
+                    .to_degrees(),
+                false => lat_min + rng.f64() * (lat_max - lat_min),
+            };
+            let lng = lng_min + rng.f64() * (lng_max - lng_min);
+            Weighted {
+                coords: (T::from_f64(lat), T::from_f64(lng)),
+                weight,
+            }
+        })
+        .collect()
+}
+
+/// BIC of k spherical gaussians with a shared variance, as in Pelleg and Moore's X-means,
+/// a data point counting as many times as its weight
+fn bic<T: LatLngType>(labels: &[usize], distances: &[T], weights: &[f64], k: usize) -> f64 {
+    let n: f64 = weights.iter().sum();
+    if labels.len() <= k {
+        return f64::NEG_INFINITY;
+    }
+    let mut sizes = vec![0.0; k];
+    for (&label, &weight) in labels.iter().zip(weights) {
+        sizes[label] += weight;
+    }
+    let squared: f64 = distances
+        .iter()
+        .zip(weights)
+        .map(|(d, w)| d.to_f64().powi(2) * w)
+        .sum();
+    let variance = (squared / (DIMENSIONS * (n - k as f64))).max(f64::MIN_POSITIVE);
+    let log_likelihood: f64 = sizes
+        .iter()
+        .filter(|&&size| size > 0.0)
+        .map(|&size| {
+            size * (size / n).ln()
+                - size * DIMENSIONS / 2.0 * (2.0 * std::f64::consts::PI * variance).ln()
+                - (size - k as f64) / 2.0
+        })
+        .sum();
+    let parameters = (k - 1) as f64 + DIMENSIONS * k as f64 + 1.0;
+    log_likelihood - parameters / 2.0 * n.ln()
+}
+
+/// the k farthest below the chord of the normalized inertia curve, the first k when there
+/// are fewer than three or the curve is flat
+fn elbow<T: LatLngType>(scores: &[KScore<T>]) -> usize {
+    let (first, last) = (&scores[0], &scores[scores.len() - 1]);
+    let (high, low) = (first.inertia.to_f64(), last.inertia.to_f64());
+    if scores.len() < 3 || high <= low {
+        return first.k;
+    }
+    let span = (last.k - first.k) as f64;
+    scores
+        .iter()
+        .map(|score| {
+            let x = (score.k - first.k) as f64 / span;
+            let y = (score.inertia.to_f64() - low) / (high - low);
+            (score.k, (1.0 - x) - y)
+        })
+        .fold((first.k, 0.0), |best, (k, below)| {
+            if below > best.1 {
+                (k, below)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+/// the smallest k with gap(k) >= gap(k + 1) - error(k + 1), the largest gap otherwise
+fn gap<T: LatLngType>(scores: &[KScore<T>]) -> usize {
+    scores
+        .windows(2)
+        .find(|pair| pair[0].gap >= pair[1].gap - pair[1].gap_error)
+        .map(|pair| pair[0].k)
+        .unwrap_or_else(|| {
+            scores
+                .iter()
+                .fold(
+                    &scores[0],
+                    |best, score| if score.gap > best.gap { score } else { best },
+                )
+                .k
+        })
+}
@@ -0,0 +1,279 @@
+//! Elkan and Hamerly assignment, the triangle inequality lets most points keep their
+//! node without measuring the distance to every centroid
+//!
+//! bounds are kept in f64 and widened by [SLACK] times the distances involved on every
+//! update, so rounding in the metric can never exclude a centroid that the plain loop
+//! would have picked. distances that decide an assignment are always measured exactly
+//! and compared the same way as [crate::kmeans::closest_centroid], ties included
+use crate::distance::DistanceMetric;
+use crate::kmeans::Algorithm;
+use crate::user_data::LatLngType;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// relative error allowed in a computed distance, generous enough for f32 metrics
+const SLACK: f64 = 1e-5;
+
+fn widen<T: LatLngType>(distance: T) -> f64 {
+    distance.to_f64() * (1.0 + SLACK)
+}
+
+fn narrow<T: LatLngType>(distance: T) -> f64 {
+    distance.to_f64() * (1.0 - SLACK)
+}
+
+/// coordinate, label, upper bound and lower bounds of one point
+type PointState<'a, T> = (((&'a (T, T), &'a mut usize), &'a mut f64), &'a mut [f64]);
+
+/// per point bounds carried between the rounds of one run
+pub(crate) struct Bounds {
+    algorithm: Algorithm,
+    width: usize,
+    /// upper bound on the distance of each point to its own centroid
+    upper: Vec<f64>,
+    /// `width` lower bounds per point, one per centroid for Elkan and one on the
+    /// second closest centroid for Hamerly
+    lower: Vec<f64>,
+    /// false until the first round and after the centroids were renumbered
+    valid: bool,
+}
+
+impl Bounds {
+    /// None for [Algorithm::Lloyd]
+    pub(crate) fn new(algorithm: Algorithm, n: usize, k: usize) -> Option<Self> {
+        let width = match algorithm {
+            Algorithm::Lloyd => return None,
+            Algorithm::Elkan => k,
+            Algorithm::Hamerly => 1,
+        };
+        Some(Self {
+            algorithm,
+            width,
+            upper: vec![0.0; n],
+            lower: vec![0.0; n * width],
+            valid: false,
+        })
+    }
+
+    /// forget the bounds, the next round measures every distance again
+    pub(crate) fn reset(&mut self) {
+        self.valid = false;
+    }
+
+    /// loosen the bounds after every centroid moved by `shifts[j]`, the labels must be
+    /// the ones from the last [Bounds::assign]
+    pub(crate) fn shift<T: LatLngType>(&mut self, shifts: &[T], labels: &[usize]) {
+        if !self.valid {
+            return;
+        }
+        let shifts: Vec<f64> = shifts.iter().map(|shift| shift.to_f64()).collect();
+        // largest shift and the largest among the others, for the single Hamerly bound
+        let mut largest = (usize::MAX, 0.0);
+        let mut second = 0.0;
+        for (j, &shift) in shifts.iter().enumerate() {
+            if shift > largest.1 {
+                second = largest.1;
+                largest = (j, shift);
+            } else if shift > second {
+                second = shift;
+            }
+        }
+        let algorithm = self.algorithm;
+        let width = self.width;
+        for ((upper, lower), &label) in self
+            .upper
+            .iter_mut()
+            .zip(self.lower.chunks_mut(width))
+            .zip(labels)
+        {
+            *upper = (*upper + shifts[label]) * (1.0 + SLACK);
+            match algorithm {
+                Algorithm::Elkan => {
+                    for (bound, &shift) in lower.iter_mut().zip(&shifts) {
+                        *bound = (*bound - shift - SLACK * (*bound + shift)).max(0.0);
+                    }
+                }
+                _ => {
+                    let shift = if label == largest.0 {
+                        second
+                    } else {
+                        largest.1
+                    };
+                    lower[0] = (lower[0] - shift - SLACK * (lower[0] + shift)).max(0.0);
+                }
+            }
+        }
+    }
+
+    /// same labels as [crate::parallel::assign], without the distances. `centroids` may
+    /// be fewer than the k the bounds were created for but never more
+    pub(crate) fn assign<T, M>(
+        &mut self,
+        coords: &[(T, T)],
+        centroids: &[(T, T)],
+        metric: &M,
+        labels: &mut [usize],
+    ) where
+        T: LatLngType,
+        M: DistanceMetric<T>,
+    {
+        let k = centroids.len();
+        let valid = self.valid;
+
+        // half the distance between every pair of centroids and to each one's closest
+        // neighbour, a point closer than that to its centroid cannot be closer to another
+        let mut half = vec![0.0; k * k];
+        let mut nearest = vec![f64::MAX; k];
+        for i in 0..k {
+            for j in i + 1..k {
+                let distance = narrow(metric.distance(&centroids[i], &centroids[j])) / 2.0;
+                half[i * k + j] = distance;
+                half[j * k + i] = distance;
+                nearest[i] = nearest[i].min(distance);
+                nearest[j] = nearest[j].min(distance);
+            }
+        }
+
+        let algorithm = self.algorithm;
+        let assign_one = |(((coord, label), upper), lower): PointState<T>| {
+            if !valid {
+                full_scan(coord, centroids, metric, algorithm, label, upper, lower);
+                return;
+            }
+            match algorithm {
+                Algorithm::Elkan => elkan(
+                    coord, centroids, metric, &half, &nearest, label, upper, lower,
+                ),
+                _ => hamerly(coord, centroids, metric, &nearest, label, upper, lower),
+            }
+        };
+        #[cfg(feature = "parallel")]
+        coords
+            .par_iter()
+            .zip(labels.par_iter_mut())
+            .zip(self.upper.par_iter_mut())
+            .zip(self.lower.par_chunks_mut(self.width))
+            .for_each(assign_one);
+        #[cfg(not(feature = "parallel"))]
+        coords
+            .iter()
+            .zip(labels.iter_mut())
+            .zip(self.upper.iter_mut())
+            .zip(self.lower.chunks_mut(self.width))
+            .for_each(assign_one);
+        self.valid = true;
+    }
+}
+
+/// measure every centroid and set the bounds from scratch
+fn full_scan<T, M>(
+    coord: &(T, T),
+    centroids: &[(T, T)],
+    metric: &M,
+    algorithm: Algorithm,
+    label: &mut usize,
+    upper: &mut f64,
+    lower: &mut [f64],
+) where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let mut best: (usize, T) = (usize::default(), T::max_value());
+    let mut second = T::max_value();
+    for (j, centroid) in centroids.iter().enumerate() {
+        let distance = metric.distance(centroid, coord);
+        if algorithm == Algorithm::Elkan {
+            lower[j] = narrow(distance);
+        }
+        if distance < best.1 {
+            second = best.1;
+            best = (j, distance);
+        } else if distance < second {
+            second = distance;
+        }
+    }
+    *label = best.0;
+    *upper = widen(best.1);
+    if algorithm == Algorithm::Hamerly {
+        lower[0] = narrow(second);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn elkan<T, M>(
+    coord: &(T, T),
+    centroids: &[(T, T)],
+    metric: &M,
+    half: &[f64],
+    nearest: &[f64],
+    label: &mut usize,
+    upper: &mut f64,
+    lower: &mut [f64],
+) where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let k = centroids.len();
+    let mut assigned = *label;
+    if *upper < nearest[assigned] {
+        return;
+    }
+    let mut best: Option<T> = None;
+    for j in 0..k {
+        if j == assigned || *upper < lower[j] || *upper < half[assigned * k + j] {
+            continue;
+        }
+        let best_distance = match best {
+            Some(distance) => distance,
+            None => {
+                let distance = metric.distance(&centroids[assigned], coord);
+                *upper = widen(distance);
+                lower[assigned] = narrow(distance);
+                best = Some(distance);
+                if *upper < lower[j] || *upper < half[assigned * k + j] {
+                    continue;
+                }
+                distance
+            }
+        };
+        let distance = metric.distance(&centroids[j], coord);
+        lower[j] = narrow(distance);
+        if distance < best_distance || (distance == best_distance && j < assigned) {
+            assigned = j;
+            best = Some(distance);
+            *upper = widen(distance);
+        }
+    }
+    *label = assigned;
+}
+
+fn hamerly<T, M>(
+    coord: &(T, T),
+    centroids: &[(T, T)],
+    metric: &M,
+    nearest: &[f64],
+    label: &mut usize,
+    upper: &mut f64,
+    lower: &mut [f64],
+) where
+    T: LatLngType,
+    M: DistanceMetric<T>,
+{
+    let bound = nearest[*label].max(lower[0]);
+    if *upper < bound {
+        return;
+    }
+    *upper = widen(metric.distance(&centroids[*label], coord));
+    if *upper < bound {
+        return;
+    }
+    full_scan(
+        coord,
+        centroids,
+        metric,
+        Algorithm::Hamerly,
+        label,
+        upper,
+        lower,
+    );
+}
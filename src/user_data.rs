@@ -10,7 +10,7 @@ const MILES_F32: f32 = 3960.0;
 ///
 /// Example:
 /// ```
-/// use kmeans::user_data::UserDataType;
+/// use coordinate_cluster::user_data::UserDataType;
 ///
 /// pub struct SomeUserData {
 ///        pub latitude: f32,
@@ -35,11 +35,42 @@ where
     T: LatLngType,
 {
     fn get_coords(&self) -> (T, T);
+
+    /// how much the data point counts, e.g. the volume of an aggregated site. a weight
+    /// of 3 pulls centroids and adds to distance totals and scores like three copies of
+    /// the point would. must be finite and above 0
+    fn weight(&self) -> f64 {
+        1.0
+    }
+}
+
+/// plain (lat, lng) tuples can be clustered directly
+impl<T: LatLngType> UserDataType<T> for (T, T) {
+    fn get_coords(&self) -> (T, T) {
+        *self
+    }
+}
+
+/// lets clusters borrow the caller's data points instead of cloning them
+impl<T, DATAPOINT> UserDataType<T> for &DATAPOINT
+where
+    T: LatLngType,
+    DATAPOINT: UserDataType<T> + ?Sized,
+{
+    fn get_coords(&self) -> (T, T) {
+        (**self).get_coords()
+    }
+
+    fn weight(&self) -> f64 {
+        (**self).weight()
+    }
 }
 
 /// stores the generic bounds required for generic K throughout
 pub trait LatLngType:
     Copy
+    + Send
+    + Sync
     + Default
     + Debug
     + PartialEq
@@ -66,12 +97,16 @@ pub trait LatLngType:
     fn to_f64(self) -> f64;
     fn from_usize(value: usize) -> Self;
     fn to_usize(self) -> usize;
-    fn rand(min: Option<Self>, max: Option<Self>) -> Self;
+    /// uniform in min..max, or in 0..1 without bounds, drawn from the given generator
+    fn rand_with(rng: &mut fastrand::Rng, min: Option<Self>, max: Option<Self>) -> Self;
     fn is_nan(self) -> bool;
     fn min(self, other: Self) -> Self;
     fn max(self, other: Self) -> Self;
     fn max_value() -> Self;
+    /// great circle distance in miles on a 3960 mile sphere
     fn haversine(coord1: &(Self, Self), coord2: &(Self, Self)) -> Self;
+    /// great circle distance on a sphere of the given radius, in the radius' units
+    fn haversine_with_radius(coord1: &(Self, Self), coord2: &(Self, Self), radius: Self) -> Self;
 }
 
 impl LatLngType for f32 {
@@ -96,11 +131,11 @@ impl LatLngType for f32 {
     fn to_usize(self) -> usize {
         self as usize
     }
-    fn rand(min: Option<Self>, max: Option<Self>) -> Self {
+    fn rand_with(rng: &mut fastrand::Rng, min: Option<Self>, max: Option<Self>) -> Self {
         if let (Some(min), Some(max)) = (min, max) {
-            fastrand::f32() * (max - min) + min
+            rng.f32() * (max - min) + min
         } else {
-            fastrand::f32()
+            rng.f32()
         }
     }
     fn is_nan(self) -> bool {
@@ -116,6 +151,9 @@ impl LatLngType for f32 {
         f32::MAX
     }
     fn haversine(coord1: &(Self, Self), coord2: &(Self, Self)) -> Self {
+        Self::haversine_with_radius(coord1, coord2, MILES_F32)
+    }
+    fn haversine_with_radius(coord1: &(Self, Self), coord2: &(Self, Self), radius: Self) -> Self {
         let b = |a: &Self| 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
 
         let d_lat = (coord2.0 - coord1.0) * RAD_PER_DEG_F32;
@@ -126,7 +164,7 @@ impl LatLngType for f32 {
         let a = ((d_lat / 2.0).sin()) * ((d_lat / 2.0).sin())
             + ((d_lon / 2.0).sin()) * ((d_lon / 2.0).sin()) * (lat1.cos()) * (lat2.cos());
 
-        b(&a) * MILES_F32
+        b(&a) * radius
     }
 }
 
@@ -152,11 +190,11 @@ impl LatLngType for f64 {
     fn to_usize(self) -> usize {
         self as usize
     }
-    fn rand(min: Option<Self>, max: Option<Self>) -> Self {
+    fn rand_with(rng: &mut fastrand::Rng, min: Option<Self>, max: Option<Self>) -> Self {
         if let (Some(min), Some(max)) = (min, max) {
-            fastrand::f64() * (max - min) + min
+            rng.f64() * (max - min) + min
         } else {
-            fastrand::f64()
+            rng.f64()
         }
     }
     fn is_nan(self) -> bool {
@@ -172,6 +210,9 @@ impl LatLngType for f64 {
         f64::MAX
     }
     fn haversine(coord1: &(Self, Self), coord2: &(Self, Self)) -> Self {
+        Self::haversine_with_radius(coord1, coord2, MILES_F64)
+    }
+    fn haversine_with_radius(coord1: &(Self, Self), coord2: &(Self, Self), radius: Self) -> Self {
         let b = |a: &Self| 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
 
         let d_lat = (coord2.0 - coord1.0) * RAD_PER_DEG_F64;
@@ -182,6 +223,6 @@ impl LatLngType for f64 {
         let a = ((d_lat / 2.0).sin()) * ((d_lat / 2.0).sin())
             + ((d_lon / 2.0).sin()) * ((d_lon / 2.0).sin()) * (lat1.cos()) * (lat2.cos());
 
-        b(&a) * MILES_F64
+        b(&a) * radius
     }
 }